@@ -1,6 +1,8 @@
+#[cfg(feature = "coolq")]
 use cqrs_builder::AppJson;
 
 fn main() {
+    #[cfg(feature = "coolq")]
     AppJson::new("com.bdbai.moodle-sentinel")
         .name("Moodle Sentinel".to_owned())
         .author("bdbai <bdbaiapp@163.com>".to_owned())
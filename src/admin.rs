@@ -0,0 +1,315 @@
+use crate::command::{Command, CommandKind};
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::locale::{tr, trf, Language};
+use crate::messenger::{log, send_to_tenant, LogLevel};
+use crate::subscribe::reset_failures;
+use crate::tenant::Tenant;
+use crate::CONN;
+use rusqlite::{params, OptionalExtension};
+
+/// How many users "用户" shows on each page
+const USERS_PER_PAGE: u32 = 20;
+
+#[derive(Debug)]
+struct Stats {
+    users: u32,
+    expired_users: u32,
+    banned_users: u32,
+    courses: u32,
+    group_subscriptions: u32,
+    self_subscriptions: u32,
+    telegram_subscriptions: u32,
+    webhook_subscriptions: u32,
+    email_subscriptions: u32,
+    groups: u32,
+    telegram_chats: u32,
+}
+
+async fn get_stats() -> Result<Stats, Error> {
+    let conn = CONN.lock().await;
+    let stats = conn.query_row(
+        "SELECT (SELECT COUNT(*) FROM `user`), \
+        (SELECT COUNT(*) FROM `user` WHERE `token_expired` = 1), \
+        (SELECT COUNT(*) FROM `user` WHERE `banned` = 1), \
+        (SELECT COUNT(*) FROM (\
+            SELECT `course_id` FROM `user_course_group` \
+            UNION SELECT `course_id` FROM `user_course_self` \
+            UNION SELECT `course_id` FROM `user_course_telegram` \
+            UNION SELECT `course_id` FROM `user_course_webhook` \
+            UNION SELECT `course_id` FROM `user_course_email`)), \
+        (SELECT COUNT(*) FROM `user_course_group`), \
+        (SELECT COUNT(*) FROM `user_course_self`), \
+        (SELECT COUNT(*) FROM `user_course_telegram`), \
+        (SELECT COUNT(*) FROM `user_course_webhook`), \
+        (SELECT COUNT(*) FROM `user_course_email`), \
+        (SELECT COUNT(DISTINCT `group_qq`) FROM `user_course_group`), \
+        (SELECT COUNT(DISTINCT `chat_id`) FROM `user_course_telegram`)",
+        params![],
+        |row| {
+            Ok(Stats {
+                users: row.get(0)?,
+                expired_users: row.get(1)?,
+                banned_users: row.get(2)?,
+                courses: row.get(3)?,
+                group_subscriptions: row.get(4)?,
+                self_subscriptions: row.get(5)?,
+                telegram_subscriptions: row.get(6)?,
+                webhook_subscriptions: row.get(7)?,
+                email_subscriptions: row.get(8)?,
+                groups: row.get(9)?,
+                telegram_chats: row.get(10)?,
+            })
+        },
+    )?;
+    Ok(stats)
+}
+
+fn format_stats(stats: &Stats, language: Language) -> String {
+    trf(
+        language,
+        "admin.stats",
+        &[
+            &stats.users,
+            &stats.expired_users,
+            &stats.banned_users,
+            &stats.courses,
+            &(stats.group_subscriptions
+                + stats.self_subscriptions
+                + stats.telegram_subscriptions
+                + stats.webhook_subscriptions
+                + stats.email_subscriptions),
+            &stats.group_subscriptions,
+            &stats.self_subscriptions,
+            &stats.telegram_subscriptions,
+            &stats.webhook_subscriptions,
+            &stats.email_subscriptions,
+            &stats.groups,
+            &stats.telegram_chats,
+        ],
+    )
+}
+
+async fn list_users(page: u32, language: Language) -> Result<String, Error> {
+    let conn = CONN.lock().await;
+    let total: u32 = conn.query_row("SELECT COUNT(*) FROM `user`", params![], |row| row.get(0))?;
+    let mut stmt = conn.prepare_cached(
+        "SELECT `u`.`id`, `u`.`nickname`, `u`.`qq`, `u`.`telegram_id`, \
+        `u`.`token_expired`, `u`.`banned`, \
+        (SELECT COUNT(*) FROM `user_course_group` WHERE `user_id` = `u`.`id`) \
+        + (SELECT COUNT(*) FROM `user_course_self` WHERE `user_id` = `u`.`id`) \
+        + (SELECT COUNT(*) FROM `user_course_telegram` WHERE `user_id` = `u`.`id`) \
+        + (SELECT COUNT(*) FROM `user_course_webhook` WHERE `user_id` = `u`.`id`) \
+        + (SELECT COUNT(*) FROM `user_course_email` WHERE `user_id` = `u`.`id`) \
+        FROM `user` AS 'u' ORDER BY `u`.`id` LIMIT ?1 OFFSET ?2",
+    )?;
+    let mut rows = stmt.query(params![USERS_PER_PAGE, (page - 1) * USERS_PER_PAGE])?;
+    let pages = total.div_ceil(USERS_PER_PAGE).max(1);
+    let mut msg = trf(language, "admin.users.title", &[&total, &page, &pages]);
+    while let Some(row) = rows.next()? {
+        let id: u32 = row.get(0)?;
+        let nickname: String = row.get(1)?;
+        let qq: Option<i64> = row.get(2)?;
+        let telegram_id: Option<i64> = row.get(3)?;
+        let token_expired: bool = row.get(4)?;
+        let banned: bool = row.get(5)?;
+        let subscriptions: u32 = row.get(6)?;
+        msg.push_str(format!("\n{} {}", id, nickname).as_str());
+        if let Some(qq) = qq {
+            msg.push_str(format!(" QQ {}", qq).as_str());
+        }
+        if let Some(telegram_id) = telegram_id {
+            msg.push_str(format!(" Telegram {}", telegram_id).as_str());
+        }
+        msg.push_str(trf(language, "admin.users.subscriptions", &[&subscriptions]).as_str());
+        if token_expired {
+            msg.push_str(tr(language, "admin.users.expired"));
+        }
+        if banned {
+            msg.push_str(tr(language, "admin.users.banned"));
+        }
+    }
+    if page < pages {
+        msg.push_str(trf(language, "admin.users.next", &[&(page + 1)]).as_str());
+    }
+    Ok(msg)
+}
+
+async fn list_groups(language: Language) -> Result<String, Error> {
+    let conn = CONN.lock().await;
+    let mut group_stmt = conn.prepare_cached(
+        "SELECT `group_qq`, COUNT(*), SUM(`failure_count` >= ?1) FROM `user_course_group` \
+        GROUP BY `group_qq` ORDER BY `group_qq`",
+    )?;
+    let mut telegram_stmt = conn.prepare_cached(
+        "SELECT `chat_id`, COUNT(*), SUM(`failure_count` >= ?1) FROM `user_course_telegram` \
+        GROUP BY `chat_id` ORDER BY `chat_id`",
+    )?;
+    let threshold = CONFIG.schedule.failure_threshold;
+    let chats = group_stmt
+        .query_map(params![threshold], |row| {
+            Ok((
+                tr(language, "admin.groups.qq"),
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+            ))
+        })?
+        .chain(telegram_stmt.query_map(params![threshold], |row| {
+            Ok(("Telegram", row.get(0)?, row.get(1)?, row.get(2)?))
+        })?)
+        .collect::<Result<Vec<(&str, i64, u32, u32)>, _>>()?;
+    if chats.is_empty() {
+        return Ok(tr(language, "admin.groups.empty").to_string());
+    }
+    let mut msg = trf(language, "admin.groups.title", &[&chats.len()]);
+    for (kind, id, count, stopped) in chats {
+        msg.push_str(trf(language, "admin.groups.item", &[&kind, &id, &count]).as_str());
+        if stopped > 0 {
+            msg.push_str(trf(language, "admin.groups.paused", &[&stopped]).as_str());
+        }
+    }
+    Ok(msg)
+}
+
+/// Send a message to every chat with subscriptions. Returns the number of
+/// chats sent to.
+async fn broadcast(text: &str) -> Result<usize, Error> {
+    let tenants = {
+        let conn = CONN.lock().await;
+        let mut group_stmt =
+            conn.prepare_cached("SELECT DISTINCT `group_qq` FROM `user_course_group`")?;
+        let mut self_stmt = conn.prepare_cached(
+            "SELECT DISTINCT `u`.`qq` FROM `user_course_self` AS 's' \
+            INNER JOIN `user` AS 'u' ON `u`.`id` = `s`.`user_id` WHERE `u`.`qq` IS NOT NULL",
+        )?;
+        let mut telegram_stmt =
+            conn.prepare_cached("SELECT DISTINCT `chat_id` FROM `user_course_telegram`")?;
+        let tenants = group_stmt
+            .query_map(params![], |row| Ok((Tenant::Group(row.get(0)?), 0)))?
+            .chain(self_stmt.query_map(params![], |row| Ok((Tenant::SenderSelf, row.get(0)?)))?)
+            .chain(
+                telegram_stmt.query_map(params![], |row| Ok((Tenant::Telegram(row.get(0)?), 0)))?,
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+        tenants
+    };
+    for (tenant, user_qq) in tenants.iter() {
+        send_to_tenant(*tenant, *user_qq, text);
+    }
+    log(
+        LogLevel::Info,
+        "admin",
+        format!("已广播到 {} 个聊天：{}", tenants.len(), text),
+    );
+    Ok(tenants.len())
+}
+
+/// Ban or unban a user. Subscriptions of banned users are not checked.
+/// Returns the nickname of the user.
+async fn set_banned(user_id: u32, banned: bool) -> Result<String, Error> {
+    let conn = CONN.lock().await;
+    let nickname: Option<String> = conn
+        .query_row(
+            "SELECT `nickname` FROM `user` WHERE `id` = ?1",
+            params![user_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let nickname = nickname.ok_or(Error::NotExist)?;
+    conn.execute(
+        "UPDATE `user` SET `banned` = ?1 WHERE `id` = ?2",
+        params![banned, user_id],
+    )?;
+    Ok(nickname)
+}
+
+/// Run a command of the admin console. `args` is the rest of the message
+/// after the command name. Replies are in `language`.
+pub async fn run_admin_command(command: &Command, args: &str, language: Language) -> String {
+    let mut params = args.split_ascii_whitespace();
+    let usage = command.usage(language);
+    let msg = match command.kind {
+        CommandKind::Stats => get_stats()
+            .await
+            .map(|stats| format_stats(&stats, language)),
+        CommandKind::Users => match params.next().map(|p| p.parse::<u32>()) {
+            None => list_users(1, language).await,
+            Some(Ok(page)) if page > 0 => list_users(page, language).await,
+            Some(_) => return usage,
+        },
+        CommandKind::Groups => list_groups(language).await,
+        CommandKind::ResetFailures => {
+            let params: Vec<_> = params.collect();
+            // QQ groups unless a Telegram chat is given explicitly, as the IDs
+            // may collide
+            let (tenant, course_id) = match params.as_slice() {
+                [group_qq, course_id] => (group_qq.parse().map(Tenant::Group), course_id),
+                ["telegram", chat_id, course_id] => {
+                    (chat_id.parse().map(Tenant::Telegram), course_id)
+                }
+                _ => return usage,
+            };
+            match (tenant, course_id.parse::<u32>()) {
+                (Ok(tenant), Ok(course_id)) => {
+                    match reset_failures(tenant, 0, Some(course_id)).await {
+                        Ok(0) => Ok(tr(language, "admin.reset.none").to_string()),
+                        Ok(count) => Ok(trf(language, "admin.reset.done", &[&count])),
+                        Err(err) => Err(err),
+                    }
+                }
+                _ => return usage,
+            }
+        }
+        CommandKind::Broadcast => match args.trim() {
+            "" => return usage,
+            text => broadcast(text)
+                .await
+                .map(|count| trf(language, "admin.broadcast.done", &[&count])),
+        },
+        CommandKind::Ban => {
+            let user_id = match params.next().map(|p| p.parse::<u32>()) {
+                Some(Ok(user_id)) => user_id,
+                _ => return usage,
+            };
+            let banned = match params.next() {
+                None => true,
+                Some("解除") | Some("undo") => false,
+                Some(_) => return usage,
+            };
+            match set_banned(user_id, banned).await {
+                Ok(nickname) if banned => {
+                    Ok(trf(language, "admin.ban.done", &[&user_id, &nickname]))
+                }
+                Ok(nickname) => Ok(trf(language, "admin.ban.undone", &[&user_id, &nickname])),
+                Err(Error::NotExist) => Ok(trf(language, "admin.ban.not_found", &[&user_id])),
+                Err(err) => Err(err),
+            }
+        }
+        _ => return usage,
+    };
+    msg.unwrap_or_else(|e| e.localize(language))
+}
+
+#[test]
+fn format_stats_test() {
+    let stats = Stats {
+        users: 10,
+        expired_users: 2,
+        banned_users: 1,
+        courses: 5,
+        group_subscriptions: 3,
+        self_subscriptions: 4,
+        telegram_subscriptions: 1,
+        webhook_subscriptions: 0,
+        email_subscriptions: 2,
+        groups: 2,
+        telegram_chats: 1,
+    };
+    assert_eq!(
+        format_stats(&stats, Language::Chinese),
+        "用户 10 个（登录过期 2，已封禁 1）\n订阅的课程 5 个\n\
+        订阅 10 个：群 3，私聊 4，Telegram 1，webhook 0，邮件 2\n\
+        QQ 群 2 个，Telegram 聊天 1 个"
+    );
+}
@@ -0,0 +1,283 @@
+use crate::check::{deliver_to_course_tenants, format_time, TICK};
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::locale::{tr, trf, Language, Localized};
+use crate::messenger::{log, LogLevel};
+use crate::moodle::{get_assignments, Assignment};
+use crate::subscribe::get_token_courses;
+use crate::CONN;
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+use tokio::time::{delay_for, Duration, Instant};
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct AssignmentConfig {
+    /// Interval in seconds between two refreshes of assignment details
+    pub interval: u64,
+    /// Reminders are sent these many seconds before the due date
+    pub reminders: Vec<i64>,
+}
+
+impl Default for AssignmentConfig {
+    fn default() -> Self {
+        AssignmentConfig {
+            interval: 60 * 30,
+            reminders: vec![60 * 60 * 24 * 3, 60 * 60 * 24, 60 * 60],
+        }
+    }
+}
+
+pub async fn start_assignment_loop() {
+    // Avoid msg spam at startup
+    if let Err(e) = refresh_assignments().await {
+        log(
+            LogLevel::Error,
+            "assignment",
+            format!("初始作业信息更新失败：{:#?}", e),
+        );
+    }
+    let mut last_refresh = Instant::now();
+    loop {
+        delay_for(TICK).await;
+        if last_refresh.elapsed() >= Duration::from_secs(CONFIG.assignment.interval) {
+            last_refresh = Instant::now();
+            match refresh_assignments().await {
+                Ok(msgs) => deliver_to_course_tenants(msgs).await,
+                Err(e) => {
+                    log(
+                        LogLevel::Error,
+                        "assignment",
+                        format!("无法更新作业信息，{:#?}", e),
+                    );
+                }
+            }
+        }
+        match take_due_reminders().await {
+            Ok(msgs) => deliver_to_course_tenants(msgs).await,
+            Err(e) => {
+                log(
+                    LogLevel::Error,
+                    "assignment",
+                    format!("无法检查作业提醒，{:#?}", e),
+                );
+            }
+        }
+    }
+}
+
+fn format_offset(seconds: i64, language: Language) -> String {
+    if seconds % (60 * 60 * 24) == 0 {
+        trf(language, "offset.days", &[&(seconds / (60 * 60 * 24))])
+    } else if seconds % (60 * 60) == 0 {
+        trf(language, "offset.hours", &[&(seconds / (60 * 60))])
+    } else {
+        trf(language, "offset.minutes", &[&(seconds / 60)])
+    }
+}
+
+fn format_deadline_change(
+    course_name: &str,
+    assignment: &Assignment,
+    due_date: i64,
+    cut_off_date: i64,
+    language: Language,
+) -> String {
+    let mut changes = Vec::new();
+    if due_date != assignment.due_date {
+        changes.push(match assignment.due_date {
+            0 => tr(language, "assignment.no_due_date").to_string(),
+            d => trf(
+                language,
+                "assignment.due_date",
+                &[&format_time(d, language)],
+            ),
+        });
+    }
+    if cut_off_date != assignment.cut_off_date {
+        changes.push(match assignment.cut_off_date {
+            0 => tr(language, "assignment.no_cut_off_date").to_string(),
+            d => trf(
+                language,
+                "assignment.cut_off_date",
+                &[&format_time(d, language)],
+            ),
+        });
+    }
+    trf(
+        language,
+        "assignment.changed",
+        &[
+            &course_name,
+            &assignment.name,
+            &changes.join(tr(language, "list.comma")),
+        ],
+    )
+}
+
+/// Fetch assignments of all subscribed courses and save them. Returns
+/// notifications about changed deadlines.
+async fn refresh_assignments() -> Result<Vec<(u32, Localized)>, Error> {
+    let token_courses = get_token_courses().await?;
+    let mut msgs = Vec::new();
+    for (token, course_ids) in token_courses {
+        let courses = match get_assignments(token, course_ids.as_slice()).await {
+            Ok(a) => a.courses,
+            Err(e) => {
+                log(
+                    LogLevel::Error,
+                    "assignment",
+                    format!("获取课程 {:?} 的作业失败 {:#?}", course_ids, e),
+                );
+                continue;
+            }
+        };
+        let conn = CONN.lock().await;
+        let mut select_stmt = conn.prepare_cached(
+            "SELECT `due_date`, `cut_off_date` FROM `assignment` WHERE `assignment_id` = ?1",
+        )?;
+        let mut insert_stmt = conn.prepare_cached(
+            "INSERT INTO `assignment` (`assignment_id`, `course_id`, `module_id`, `course_name`, \
+            `name`, `intro`, `due_date`, `cut_off_date`, `updated_at`) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        let mut update_stmt = conn.prepare_cached(
+            "UPDATE `assignment` SET `course_name` = ?1, `name` = ?2, `intro` = ?3, \
+            `due_date` = ?4, `cut_off_date` = ?5, `updated_at` = ?6 WHERE `assignment_id` = ?7",
+        )?;
+        let now = Utc::now().naive_utc();
+        for course in courses {
+            for assignment in course.assignments {
+                let existing: Option<(i64, i64)> = select_stmt
+                    .query_row(params![assignment.id], |row| Ok((row.get(0)?, row.get(1)?)))
+                    .optional()?;
+                match existing {
+                    // New assignments are announced as new course modules
+                    None => {
+                        insert_stmt.execute(params![
+                            assignment.id,
+                            course.id,
+                            assignment.module_id,
+                            course.full_name,
+                            assignment.name,
+                            assignment.intro,
+                            assignment.due_date,
+                            assignment.cut_off_date,
+                            now
+                        ])?;
+                    }
+                    Some((due_date, cut_off_date)) => {
+                        update_stmt.execute(params![
+                            course.full_name,
+                            assignment.name,
+                            assignment.intro,
+                            assignment.due_date,
+                            assignment.cut_off_date,
+                            now,
+                            assignment.id
+                        ])?;
+                        if due_date != assignment.due_date
+                            || cut_off_date != assignment.cut_off_date
+                        {
+                            let course_name = course.full_name.as_str();
+                            msgs.push((
+                                course.id,
+                                Localized::new(|language| {
+                                    format_deadline_change(
+                                        course_name,
+                                        &assignment,
+                                        due_date,
+                                        cut_off_date,
+                                        language,
+                                    )
+                                }),
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(msgs)
+}
+
+/// Find assignments entering a reminder window and mark them as reminded.
+/// Only the tightest window is reminded, so that assignments discovered
+/// late do not trigger every reminder at once.
+async fn take_due_reminders() -> Result<Vec<(u32, Localized)>, Error> {
+    let reminders = &CONFIG.assignment.reminders;
+    let max_offset = match reminders.iter().max() {
+        Some(&o) => o,
+        None => return Ok(Vec::new()),
+    };
+    let now = Utc::now().timestamp();
+    let conn = CONN.lock().await;
+    let assignments: Vec<(u32, u32, String, String, i64)> = conn
+        .prepare_cached(
+            "SELECT `assignment_id`, `course_id`, `course_name`, `name`, `due_date` \
+            FROM `assignment` WHERE `due_date` > ?1 AND `due_date` <= ?2",
+        )?
+        .query_map(params![now, now + max_offset], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })?
+        .collect::<Result<_, _>>()?;
+    let mut sent_stmt = conn.prepare_cached(
+        "SELECT MIN(`offset`) FROM `assignment_reminder` \
+        WHERE `assignment_id` = ?1 AND `due_date` = ?2",
+    )?;
+    let mut insert_stmt = conn.prepare_cached(
+        "INSERT INTO `assignment_reminder` (`assignment_id`, `due_date`, `offset`, `created_at`) \
+        VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    let mut msgs = Vec::new();
+    for (assignment_id, course_id, course_name, name, due_date) in assignments {
+        let offset = match reminders.iter().filter(|&&o| due_date - now <= o).min() {
+            Some(&o) => o,
+            None => continue,
+        };
+        let sent: Option<i64> =
+            sent_stmt.query_row(params![assignment_id, due_date], |row| row.get(0))?;
+        if matches!(sent, Some(s) if s <= offset) {
+            continue;
+        }
+        insert_stmt.execute(params![
+            assignment_id,
+            due_date,
+            offset,
+            Utc::now().naive_utc()
+        ])?;
+        msgs.push((
+            course_id,
+            Localized::new(|language| {
+                trf(
+                    language,
+                    "assignment.reminder",
+                    &[
+                        &course_name,
+                        &name,
+                        &format_time(due_date, language),
+                        &format_offset(offset, language),
+                    ],
+                )
+            }),
+        ));
+    }
+    Ok(msgs)
+}
+
+#[test]
+fn format_offset_test() {
+    let zh = Language::Chinese;
+    assert_eq!(format_offset(60 * 60 * 24 * 3, zh), "3 天");
+    assert_eq!(format_offset(60 * 60 * 24, zh), "1 天");
+    assert_eq!(format_offset(60 * 60 * 36, zh), "36 小时");
+    assert_eq!(format_offset(60 * 30, zh), "30 分钟");
+    assert_eq!(format_offset(60 * 60, Language::English), "1 hour(s)");
+}
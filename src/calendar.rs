@@ -0,0 +1,308 @@
+use crate::check::{deliver_to_course_tenants, format_time, TICK};
+use crate::config::CONFIG;
+use crate::course::{course_display_name, get_cached_course_name, get_course_name};
+use crate::error::Error;
+use crate::locale::{tr, trf, Language, Localized};
+use crate::messenger::{log, LogLevel};
+use crate::moodle::{get_calendar_events, CalendarEvent};
+use crate::subscribe::get_token_courses;
+use crate::CONN;
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use tokio::time::{delay_for, Duration, Instant};
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CalendarConfig {
+    /// Interval in seconds between two checks of calendar events
+    pub interval: u64,
+    /// How many days of upcoming events are tracked
+    pub lookahead_days: i64,
+}
+
+impl Default for CalendarConfig {
+    fn default() -> Self {
+        CalendarConfig {
+            interval: 60 * 30,
+            lookahead_days: 30,
+        }
+    }
+}
+
+pub async fn start_calendar_loop() {
+    let mut last_check: Option<Instant> = None;
+    loop {
+        if let Some(last_check) = last_check {
+            delay_for(TICK).await;
+            if last_check.elapsed() < Duration::from_secs(CONFIG.calendar.interval) {
+                continue;
+            }
+        }
+        last_check = Some(Instant::now());
+        match check_calendar().await {
+            Ok(msgs) => deliver_to_course_tenants(msgs).await,
+            Err(e) => {
+                log(
+                    LogLevel::Error,
+                    "calendar",
+                    format!("无法检查日程更新，{:#?}", e),
+                );
+            }
+        }
+    }
+}
+
+/// A change to the events of a course found by a check
+enum EventChange {
+    /// Created or modified in Moodle since the course was last checked
+    New(CalendarEvent),
+    /// Renamed or rescheduled, with its previous name and start time
+    Changed(CalendarEvent, String, i64),
+    /// Missing from Moodle, with its course, name and start time
+    Removed(u32, String, i64),
+}
+
+/// When the events of each course were last checked. Courses checked for the
+/// first time are missing.
+async fn get_checked_courses() -> Result<HashMap<u32, i64>, Error> {
+    let conn = CONN.lock().await;
+    let mut stmt =
+        conn.prepare_cached("SELECT `course_id`, `checked_at` FROM `calendar_course`")?;
+    let rows = stmt
+        .query_map(params![], |row| {
+            Ok((row.get(0)?, row.get::<_, Option<i64>>(1)?.unwrap_or(0)))
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(rows)
+}
+
+async fn set_checked_courses(course_ids: &[u32], checked_at: i64) -> Result<(), Error> {
+    let conn = CONN.lock().await;
+    let mut insert_stmt = conn.prepare_cached(
+        "INSERT OR IGNORE INTO `calendar_course` (`course_id`, `created_at`) VALUES (?1, ?2)",
+    )?;
+    let mut update_stmt = conn
+        .prepare_cached("UPDATE `calendar_course` SET `checked_at` = ?1 WHERE `course_id` = ?2")?;
+    let now = Utc::now().naive_utc();
+    for course_id in course_ids {
+        insert_stmt.execute(params![course_id, now])?;
+        update_stmt.execute(params![checked_at, course_id])?;
+    }
+    Ok(())
+}
+
+/// Events of courses checked for the first time are saved silently, which
+/// also avoids msg spam at startup
+async fn check_calendar() -> Result<Vec<(u32, Localized)>, Error> {
+    let checked_courses = get_checked_courses().await?;
+    let now = Utc::now().timestamp();
+    let time_end = now + CONFIG.calendar.lookahead_days * 60 * 60 * 24;
+    let mut msgs = Vec::new();
+    for (token, course_ids) in get_token_courses().await? {
+        let events = match get_calendar_events(&token, course_ids.as_slice(), now, time_end).await {
+            Ok(e) => e.events,
+            Err(e) => {
+                log(
+                    LogLevel::Error,
+                    "calendar",
+                    format!("获取课程 {:?} 的日程失败 {:#?}", course_ids, e),
+                );
+                continue;
+            }
+        };
+        let changes = save_events(
+            course_ids.as_slice(),
+            events,
+            now,
+            time_end,
+            &checked_courses,
+        )
+        .await?;
+        for change in changes {
+            let course_id = match &change {
+                EventChange::New(event) | EventChange::Changed(event, _, _) => event.course_id,
+                EventChange::Removed(course_id, _, _) => *course_id,
+            };
+            let course_name = get_course_name(token.as_str(), course_id).await;
+            msgs.push((
+                course_id,
+                Localized::new(|language| {
+                    format_event_change(course_name.as_deref(), &change, language)
+                }),
+            ));
+        }
+        set_checked_courses(course_ids.as_slice(), now).await?;
+    }
+    Ok(msgs)
+}
+
+fn format_event_change(
+    course_name: Option<&str>,
+    change: &EventChange,
+    language: Language,
+) -> String {
+    let course_name = course_display_name(course_name, language);
+    match change {
+        EventChange::New(event) => trf(
+            language,
+            "calendar.new",
+            &[
+                &course_name,
+                &event.name,
+                &format_time(event.time_start, language),
+            ],
+        ),
+        EventChange::Changed(event, name, _) if *name == event.name => trf(
+            language,
+            "calendar.changed",
+            &[
+                &course_name,
+                &event.name,
+                &format_time(event.time_start, language),
+            ],
+        ),
+        EventChange::Changed(event, name, time) if *time == event.time_start => trf(
+            language,
+            "calendar.renamed",
+            &[&course_name, name, &event.name],
+        ),
+        EventChange::Changed(event, name, _) => trf(
+            language,
+            "calendar.renamed_changed",
+            &[
+                &course_name,
+                name,
+                &event.name,
+                &format_time(event.time_start, language),
+            ],
+        ),
+        EventChange::Removed(_, name, time_start) => trf(
+            language,
+            "calendar.removed",
+            &[&course_name, name, &format_time(*time_start, language)],
+        ),
+    }
+}
+
+/// Save the events of `course_ids` fetched between `time_start` and
+/// `time_end`, and delete the saved ones missing from them. Returns the
+/// changes to announce, none for courses missing from `checked_courses`.
+/// Assignment deadlines are announced with assignments instead.
+async fn save_events(
+    course_ids: &[u32],
+    events: Vec<CalendarEvent>,
+    time_start: i64,
+    time_end: i64,
+    checked_courses: &HashMap<u32, i64>,
+) -> Result<Vec<EventChange>, Error> {
+    let mut conn = CONN.lock().await;
+    let tx = conn.transaction()?;
+    let mut changes = Vec::new();
+    {
+        let mut select_stmt = tx.prepare_cached(
+            "SELECT `name`, `time_start` FROM `calendar_event` WHERE `event_id` = ?1",
+        )?;
+        let mut upsert_stmt = tx.prepare_cached(
+            "INSERT OR REPLACE INTO `calendar_event` \
+            (`event_id`, `course_id`, `name`, `time_start`, `time_duration`, `module_name`, \
+            `updated_at`) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        let now = Utc::now().naive_utc();
+        let mut event_ids = HashSet::new();
+        for event in events {
+            event_ids.insert(event.id);
+            let previous: Option<(String, i64)> = select_stmt
+                .query_row(params![event.id], |row| Ok((row.get(0)?, row.get(1)?)))
+                .optional()?;
+            upsert_stmt.execute(params![
+                event.id,
+                event.course_id,
+                event.name,
+                event.time_start,
+                event.time_duration,
+                event.module_name,
+                now
+            ])?;
+            let checked_at = match checked_courses.get(&event.course_id) {
+                Some(&checked_at) if event.module_name.as_deref() != Some("assign") => checked_at,
+                _ => continue,
+            };
+            match previous {
+                // Events entering the window are not new
+                None if event.time_modified >= checked_at => changes.push(EventChange::New(event)),
+                None => {}
+                Some((name, time)) if name != event.name || time != event.time_start => {
+                    changes.push(EventChange::Changed(event, name, time))
+                }
+                Some(_) => {}
+            }
+        }
+
+        let mut saved_stmt = tx.prepare_cached(
+            "SELECT `event_id`, `name`, `time_start`, `module_name` FROM `calendar_event` \
+            WHERE `course_id` = ?1 AND `time_start` >= ?2 AND `time_start` <= ?3",
+        )?;
+        let mut delete_stmt =
+            tx.prepare_cached("DELETE FROM `calendar_event` WHERE `event_id` = ?1")?;
+        for &course_id in course_ids {
+            let saved = saved_stmt
+                .query_map(params![course_id, time_start, time_end], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })?
+                .collect::<Result<Vec<(u32, String, i64, Option<String>)>, _>>()?;
+            for (event_id, name, time, module_name) in saved {
+                if event_ids.contains(&event_id) {
+                    continue;
+                }
+                delete_stmt.execute(params![event_id])?;
+                if checked_courses.contains_key(&course_id)
+                    && module_name.as_deref() != Some("assign")
+                {
+                    changes.push(EventChange::Removed(course_id, name, time));
+                }
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(changes)
+}
+
+/// List events of the given courses in the coming week.
+pub async fn list_upcoming_events(course_ids: &[u32], language: Language) -> Result<String, Error> {
+    let now = Utc::now().timestamp();
+    let events: Vec<(u32, String, i64)> = {
+        let conn = CONN.lock().await;
+        let mut stmt = conn.prepare_cached(
+            "SELECT `course_id`, `name`, `time_start` FROM `calendar_event` \
+            WHERE `time_start` >= ?1 AND `time_start` < ?2 ORDER BY `time_start`",
+        )?;
+        let rows = stmt
+            .query_map(params![now, now + 60 * 60 * 24 * 7], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        rows
+    };
+    let mut lines = Vec::new();
+    for (course_id, name, time_start) in events {
+        if !course_ids.contains(&course_id) {
+            continue;
+        }
+        let course_name = get_cached_course_name(course_id)
+            .await?
+            .unwrap_or_else(|| trf(language, "calendar.course", &[&course_id]));
+        lines.push(format!(
+            "{} {} {}",
+            format_time(time_start, language),
+            course_name,
+            name
+        ));
+    }
+    Ok(if lines.is_empty() {
+        tr(language, "calendar.empty").to_string()
+    } else {
+        trf(language, "calendar.title", &[&lines.join("\n")])
+    })
+}
@@ -1,129 +1,461 @@
+use crate::config::CONFIG;
+use crate::course::{course_display_name, save_course_name};
 use crate::error::Error;
-use crate::moodle::{get_course_content, get_course_public_information, CourseModule, ModuleType};
+use crate::locale::{get_tenant_language, tr, trf, Language, Localized};
+use crate::messenger::{log, send_email, send_payload, send_to_tenant, EmailItem, LogLevel};
+use crate::moodle::{
+    get_course_content, get_course_public_information, module_url, CourseModule, ModuleType,
+};
+use crate::subscribe::get_course_tenants;
+use crate::template::{get_templates, TemplateKind, Templates};
 use crate::tenant::Tenant;
+use crate::user::{
+    get_user_contact, get_user_credential, login_moodle, save_user_token, set_token_expired,
+};
 use crate::CONN;
-use chrono::{NaiveDateTime, Utc};
-use coolq_sdk_rust::api::{add_log, send_group_msg, send_private_msg, CQLogLevel};
+use chrono::{Local, TimeZone, Utc};
 use futures::lock::Mutex;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
-use lazy_static::lazy_static;
 use rusqlite::params;
+use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::mem::replace;
 use std::ops::Deref;
 use std::sync::Arc;
 use tokio::time::{delay_for, Duration};
 
+/// Outcome of checking each course: course id, failure count and the updates
+type CourseResults = Vec<(u32, u32, Result<Vec<Update>, Error>)>;
+
 #[derive(Debug)]
 struct Notification<'a> {
     tenant: Tenant,
     user_qq: i64,
-    course_name: String,
+    course_id: u32,
+    /// `None` if the course cannot be found
+    course_name: Option<String>,
+    /// Consecutive failures of the subscription before this check
+    failure_count: u32,
     modules: &'a Result<Vec<Update>, Error>,
+    language: Language,
+    templates: &'a Templates,
+}
+
+impl Notification<'_> {
+    fn course_name(&self) -> &str {
+        course_display_name(self.course_name.as_deref(), self.language)
+    }
 }
 
+/// How often the due subscriptions are looked up
+pub const TICK: Duration = Duration::from_secs(30);
+
 pub async fn start_check_loop() {
+    let schedule = &CONFIG.schedule;
+    // The last and the next scheduled check of each course of each user
+    let mut next_checks = HashMap::new();
+    // Subscriptions to a course by the same user share the saved modules, so
+    // they are scheduled as one and checked together when the earliest of
+    // them is due.
+    let mut select_due = |subscriptions: Vec<SubscriptionData>| {
+        let now = Local::now();
+        let mut courses: HashMap<_, Vec<_>> = HashMap::new();
+        for subscription in subscriptions {
+            courses
+                .entry((subscription.user_id, subscription.course_id))
+                .or_default()
+                .push(subscription);
+        }
+        next_checks.retain(|key, _| courses.contains_key(key));
+        let mut due = Vec::new();
+        for (key, subscriptions) in courses {
+            // Paused only when every subscription is
+            let failure_count = subscriptions
+                .iter()
+                .map(|s| s.failure_count)
+                .min()
+                .unwrap_or_default();
+            let paused = schedule.is_paused(failure_count);
+            // Paused subscriptions are retried with back-off from the last
+            // check, which has updated the failure count
+            let retry_delay = |checked_at| {
+                checked_at + chrono::Duration::seconds(schedule.retry_delay(failure_count) as i64)
+            };
+            let next_check = match next_checks.get(&key) {
+                Some(&(checked_at, _)) if paused => retry_delay(checked_at),
+                Some(&(_, next_check)) => next_check,
+                // Do not retry paused subscriptions right after starting
+                None if paused => {
+                    next_checks.insert(key, (now, retry_delay(now)));
+                    continue;
+                }
+                None => now,
+            };
+            if next_check > now {
+                continue;
+            }
+            let next_check = subscriptions
+                .iter()
+                .map(|s| schedule.next_check(s.tenant, s.user_qq, s.course_id, now))
+                .min()
+                .unwrap_or(now);
+            next_checks.insert(key, (now, next_check));
+            due.extend(subscriptions);
+        }
+        due
+    };
     // Initial check
     // Avoid msg spam at startup
-    match run_check(|_| {}).await {
-        Ok(()) => {
-            add_log(CQLogLevel::INFO, "check", "初始课程内容更新检查完成").expect("Cannot send log")
-        }
-        Err(e) => add_log(
-            CQLogLevel::ERROR,
+    match run_check(&mut select_due, |_| {}).await {
+        Ok(()) => log(LogLevel::Info, "check", "初始课程内容更新检查完成"),
+        Err(e) => log(
+            LogLevel::Error,
             "check",
             format!("初始课程内容更新检查失败：{:#?}", e),
-        )
-        .expect("Cannot send log"),
+        ),
     };
     loop {
-        // TODO: 时间间隔？
-        delay_for(Duration::from_secs(60 * 5)).await;
-        if let Err(e) = run_check(|update| {
-            let msg = match update.modules.as_ref().map(|v| v.as_slice()) {
-                Ok([]) => return,
-                Ok([m]) => format!(
-                    "{} 发布了一个{}{} {}，快去看看吧",
-                    update.course_name,
-                    if m.module.user_visible {
-                        ""
-                    } else {
-                        "隐藏的"
-                    },
-                    match &m.module.content {
-                        ModuleType::Mediasite => "视频",
-                        ModuleType::Resource { .. } => "文件",
-                        ModuleType::Url { .. } => "链接",
-                        ModuleType::Folder { .. } => "文件夹",
-                        ModuleType::Page { .. } => "页面",
-                        ModuleType::Assignment => "作业",
-                        ModuleType::Other => return,
-                    },
-                    if let ModuleType::Url { contents } = &m.module.content {
-                        contents
-                            .as_ref()
-                            .and_then(|c| c.first())
-                            .map(|c| c.name.as_str())
-                            .unwrap_or(m.module.name.as_str())
-                    } else {
-                        m.module.name.as_str()
+        delay_for(TICK).await;
+        if let Err(e) = run_check(&mut select_due, |update| {
+            let msgs = match update.modules {
+                Ok(modules) => {
+                    let mut msgs = format_updates(
+                        update.course_name(),
+                        update.course_id,
+                        modules.as_slice(),
+                        update.templates,
+                    );
+                    if schedule.is_paused(update.failure_count) {
+                        msgs.insert(
+                            0,
+                            trf(update.language, "check.resumed", &[&update.course_name()]),
+                        );
                     }
-                ),
-                Ok(n) => format!(
-                    "{} 发布了 {} 个内容，快去看看吧",
-                    update.course_name,
-                    n.len()
-                ),
+                    msgs
+                }
                 Err(e) => {
-                    let _ = add_log(
-                        CQLogLevel::ERROR,
+                    log(
+                        LogLevel::Error,
                         "check",
                         format!("更新 {:#?} 出错：{:#?}", update, e),
                     );
-                    format!(
-                        "更新 {} 时出错了，将停止后续更新\n{}",
-                        update.course_name, e
-                    )
+                    let failure_count = update.failure_count + 1;
+                    if failure_count == schedule.failure_threshold {
+                        let retry_at =
+                            Utc::now().timestamp() + schedule.retry_delay(failure_count) as i64;
+                        vec![trf(
+                            update.language,
+                            "check.paused",
+                            &[
+                                &update.course_name(),
+                                &failure_count,
+                                &format_time(retry_at, update.language),
+                                &update.course_id,
+                                &e.localize(update.language),
+                            ],
+                        )]
+                    } else {
+                        vec![]
+                    }
                 }
             };
-            match update.tenant {
-                Tenant::Group(group_qq) => send_group_msg(group_qq, msg.as_str()),
-                Tenant::SenderSelf => send_private_msg(update.user_qq, msg.as_str()),
+            if msgs.is_empty() {
+                return;
             }
-            .or_else(|e| {
-                add_log(
-                    CQLogLevel::ERROR,
-                    "update",
-                    format!(
-                        "无法发送消息\"{}\"到 {:?} 因为 {:#?}",
-                        msg, update.tenant, e
+            let ret = match update.tenant {
+                Tenant::Webhook(webhook_id) => {
+                    send_payload(webhook_id, notification_payload(&update, &msgs))
+                }
+                Tenant::Email(user_id) => send_email(
+                    user_id,
+                    trf(
+                        update.language,
+                        "email.update_subject",
+                        &[&update.course_name()],
                     ),
-                )
-            })
-            .expect("Cannot add log");
+                    notification_items(&update, &msgs),
+                ),
+                _ => {
+                    for msg in msgs {
+                        send_to_tenant(update.tenant, update.user_qq, msg.as_str());
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(e) = ret {
+                log(
+                    LogLevel::Error,
+                    "send",
+                    format!("无法发送更新到 {:?} 因为 {:#?}", update.tenant, e),
+                );
+            }
         })
         .await
         {
-            add_log(CQLogLevel::ERROR, "update", format!("无法更新，{:#?}", e))
-                .expect("Cannot send cq log");
+            log(LogLevel::Error, "update", format!("无法更新，{:#?}", e));
+        }
+    }
+}
+
+/// Send messages to every tenant subscribed to the corresponding course, each
+/// in the language of the tenant.
+pub async fn deliver_to_course_tenants(msgs: Vec<(u32, Localized)>) {
+    for (course_id, msg) in msgs {
+        match get_course_tenants(course_id).await {
+            Ok(tenants) => {
+                for (tenant, user_qq) in tenants {
+                    let language = get_tenant_language(tenant, user_qq).await;
+                    send_to_tenant(tenant, user_qq, msg.get(language));
+                }
+            }
+            Err(e) => {
+                log(
+                    LogLevel::Error,
+                    "update",
+                    format!("无法读取课程 {} 的订阅，{:#?}", course_id, e),
+                );
+            }
+        }
+    }
+}
+
+pub fn format_time(timestamp: i64, language: Language) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|t| t.format(tr(language, "time.format")).to_string())
+        .unwrap_or_default()
+}
+
+fn module_type_name(content: &ModuleType, language: Language) -> Option<&'static str> {
+    let key = match content {
+        ModuleType::Mediasite => "module.video",
+        ModuleType::Resource { .. } => "module.file",
+        ModuleType::Url { .. } => "module.link",
+        ModuleType::Folder { .. } => "module.folder",
+        ModuleType::Page { .. } => "module.page",
+        ModuleType::Assignment => "module.assignment",
+        // New discussions are announced by the forum loop instead
+        ModuleType::Forum | ModuleType::Other => return None,
+    };
+    Some(tr(language, key))
+}
+
+fn module_display_name(module: &CourseModule) -> &str {
+    if let ModuleType::Url { contents } = &module.content {
+        contents
+            .as_ref()
+            .and_then(|c| c.first())
+            .map(|c| c.name.as_str())
+            .unwrap_or(module.name.as_str())
+    } else {
+        module.name.as_str()
+    }
+}
+
+/// JSON delivered to webhooks, with both the rendered messages and the raw
+/// updates.
+fn notification_payload(notification: &Notification, msgs: &[String]) -> Value {
+    let (updates, error) = match notification.modules {
+        Ok(updates) => (
+            updates
+                .iter()
+                .map(|u| {
+                    json!({
+                        "type": match u.update_type {
+                            UpdateType::Insert => "insert",
+                            UpdateType::Update(_) => "update",
+                            UpdateType::Show(_) => "show",
+                            UpdateType::Hide(_) => "hide",
+                            UpdateType::Remove(_) => "remove",
+                        },
+                        "module_id": u.module.id,
+                        "module_type": u.module.content.modname().unwrap_or("other"),
+                        "name": module_display_name(&u.module),
+                        "visible": u.module.user_visible,
+                    })
+                })
+                .collect(),
+            None,
+        ),
+        Err(e) => (vec![], Some(e.to_string())),
+    };
+    json!({
+        "type": "update",
+        "course_id": notification.course_id,
+        "course_name": notification.course_name(),
+        "text": msgs.join("\n"),
+        "updates": updates,
+        "error": error,
+    })
+}
+
+/// Entries of the mail to email subscribers, linking to each module.
+fn notification_items(notification: &Notification, msgs: &[String]) -> Vec<EmailItem> {
+    let updates = match notification.modules {
+        Ok(updates) => updates,
+        Err(_) => {
+            return msgs
+                .iter()
+                .map(|msg| EmailItem {
+                    course_name: Some(notification.course_name().to_string()),
+                    text: msg.clone(),
+                    ..Default::default()
+                })
+                .collect()
         }
+    };
+    let language = notification.language;
+    updates
+        .iter()
+        .map(|u| EmailItem {
+            course_name: Some(notification.course_name().to_string()),
+            text: tr(
+                language,
+                match u.update_type {
+                    UpdateType::Insert => "email.publish",
+                    UpdateType::Update(_) => "email.update",
+                    UpdateType::Show(_) => "email.show",
+                    UpdateType::Hide(_) => "email.hide",
+                    UpdateType::Remove(_) => "email.remove",
+                },
+            )
+            .to_string(),
+            module_type: Some(
+                module_type_name(&u.module.content, language)
+                    .unwrap_or_else(|| tr(language, "module.other"))
+                    .to_string(),
+            ),
+            module_name: Some(module_display_name(&u.module).to_string()),
+            url: match u.update_type {
+                UpdateType::Remove(_) => None,
+                _ => Some(module_url(notification.course_id, &u.module)),
+            },
+        })
+        .collect()
+}
+
+/// Variables of the templates rendering a change to a single module
+fn module_vars<'a>(
+    course_name: &str,
+    course_id: u32,
+    update: &Update,
+    language: Language,
+) -> HashMap<&'a str, String> {
+    let module = &update.module;
+    let mut vars = HashMap::new();
+    vars.insert("course_name", course_name.to_string());
+    vars.insert("module_name", module_display_name(module).to_string());
+    vars.insert(
+        "module_type",
+        module_type_name(&module.content, language)
+            .unwrap_or_else(|| tr(language, "module.other"))
+            .to_string(),
+    );
+    vars.insert("visible", module.user_visible.to_string());
+    if !matches!(update.update_type, UpdateType::Remove(_)) {
+        vars.insert("url", module_url(course_id, module));
     }
+    vars
+}
+
+/// Variables of the templates rendering changes to several modules
+fn count_vars<'a>(course_name: &str, count: usize) -> HashMap<&'a str, String> {
+    let mut vars = HashMap::new();
+    vars.insert("course_name", course_name.to_string());
+    vars.insert("count", count.to_string());
+    vars
+}
+
+/// Render the updates of a course into messages, one for each kind of change.
+fn format_updates(
+    course_name: &str,
+    course_id: u32,
+    updates: &[Update],
+    templates: &Templates,
+) -> Vec<String> {
+    let of_kind = |kind: fn(&UpdateType) -> bool| {
+        updates
+            .iter()
+            .filter(|u| kind(&u.update_type))
+            .collect::<Vec<_>>()
+    };
+    let language = templates.language();
+    let single = |kind, m: &Update| {
+        templates.render(kind, &module_vars(course_name, course_id, m, language))
+    };
+    let many = |kind, count| templates.render(kind, &count_vars(course_name, count));
+    let mut msgs = Vec::new();
+    match of_kind(|t| matches!(t, UpdateType::Insert | UpdateType::Update(_))).as_slice() {
+        [] => {}
+        [m] => {
+            if module_type_name(&m.module.content, language).is_some() {
+                let kind = match m.update_type {
+                    UpdateType::Update(_) => TemplateKind::Update,
+                    _ => TemplateKind::Publish,
+                };
+                msgs.push(single(kind, m))
+            }
+        }
+        n => {
+            let inserted = n
+                .iter()
+                .filter(|u| matches!(u.update_type, UpdateType::Insert))
+                .count();
+            if inserted > 0 {
+                msgs.push(many(TemplateKind::PublishMany, inserted));
+            }
+            if n.len() > inserted {
+                msgs.push(many(TemplateKind::UpdateMany, n.len() - inserted));
+            }
+        }
+    }
+    match of_kind(|t| matches!(t, UpdateType::Show(_))).as_slice() {
+        [] => {}
+        [m] => msgs.push(single(TemplateKind::Show, m)),
+        n => msgs.push(many(TemplateKind::ShowMany, n.len())),
+    }
+    match of_kind(|t| matches!(t, UpdateType::Hide(_))).as_slice() {
+        [] => {}
+        [m] => msgs.push(single(TemplateKind::Hide, m)),
+        n => msgs.push(many(TemplateKind::HideMany, n.len())),
+    }
+    match of_kind(|t| matches!(t, UpdateType::Remove(_))).as_slice() {
+        [] => {}
+        [m] if !m.module.name.is_empty() => msgs.push(single(TemplateKind::Remove, m)),
+        n => msgs.push(many(TemplateKind::RemoveMany, n.len())),
+    }
+    msgs
 }
 
 #[derive(Debug)]
-struct GroupData {
+struct SubscriptionData {
     token: String,
-    group_id: u32,
     course_id: u32,
-    group_qq: i64,
+    tenant: Tenant,
     user_id: u32,
+    user_qq: i64,
+    failure_count: u32,
 }
 
 #[derive(Clone, Copy, Debug)]
 enum UpdateType {
     Insert,
     Update(u32),
+    Show(u32),
+    Hide(u32),
+    Remove(u32),
+}
+
+#[derive(Debug)]
+struct ModuleRecord {
+    id: u32,
+    last_modified: Option<i32>,
+    content_hash: Option<String>,
+    visible: Option<bool>,
+    name: Option<String>,
+    removed: bool,
 }
 
 #[derive(Debug)]
@@ -134,167 +466,395 @@ struct Update {
     module: CourseModule,
 }
 
-async fn save_group_updates(updates: impl Iterator<Item = Update>) -> Result<(), Error> {
+async fn save_updates(updates: impl Iterator<Item = Update>) -> Result<(), Error> {
     let mut conn = CONN.lock().await;
     let tx = conn.transaction()?;
-    let mut update_stmt =
-        tx.prepare_cached("UPDATE `user_course_module` SET `updated_at` = ?1 WHERE `id` = ?2")?;
-    let mut insert_stmt = tx.prepare_cached("INSERT INTO `user_course_module` (`user_id`, `course_id`, `module_id`, `updated_at`) VALUES (?1, ?2, ?3, ?4)")?;
-    // TODO: check the last update timestamp from response
+    let mut update_stmt = tx.prepare_cached(
+        "UPDATE `user_course_module` SET `updated_at` = ?1, `last_modified` = ?2, \
+        `module_name` = ?3, `visible` = ?4, `content_hash` = ?5, `removed` = 0 WHERE `id` = ?6",
+    )?;
+    let mut remove_stmt = tx.prepare_cached(
+        "UPDATE `user_course_module` SET `updated_at` = ?1, `removed` = 1 WHERE `id` = ?2",
+    )?;
+    let mut insert_stmt = tx.prepare_cached(
+        "INSERT INTO `user_course_module` (`user_id`, `course_id`, `module_id`, `updated_at`, \
+        `last_modified`, `module_name`, `visible`, `content_hash`) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
     let now = Utc::now().naive_utc();
-    lazy_static! {
-        static ref EXPIRATION: time::Duration = time::Duration::minutes(1);
-    }
-    for ((user_id, module_id), (update_type, course_id)) in updates
+    for (_, update) in updates
         // Dedup
-        .map(|u| ((u.user_id, u.module.id), (u.update_type, u.course_id)))
+        .map(|u| ((u.user_id, u.module.id), u))
         .collect::<HashMap<_, _>>()
         .into_iter()
     {
-        match update_type {
+        let module = &update.module;
+        let last_modified = module.content.last_modified();
+        let content_hash = module.content.content_hash();
+        match update.update_type {
             UpdateType::Insert => {
-                insert_stmt.execute(params![user_id, course_id, module_id, now])?;
+                insert_stmt.execute(params![
+                    update.user_id,
+                    update.course_id,
+                    module.id,
+                    now,
+                    last_modified,
+                    module.name,
+                    module.user_visible,
+                    content_hash
+                ])?;
             }
-            UpdateType::Update(record_id) => {
-                update_stmt.execute(params![now, record_id])?;
+            UpdateType::Update(record_id)
+            | UpdateType::Show(record_id)
+            | UpdateType::Hide(record_id) => {
+                update_stmt.execute(params![
+                    now,
+                    last_modified,
+                    module.name,
+                    module.user_visible,
+                    content_hash,
+                    record_id
+                ])?;
+            }
+            UpdateType::Remove(record_id) => {
+                remove_stmt.execute(params![now, record_id])?;
             }
         }
     }
     drop(update_stmt);
+    drop(remove_stmt);
     drop(insert_stmt);
     tx.commit()?;
     Ok(())
 }
 
-async fn run_check(mut on_new_message: impl FnMut(Notification)) -> Result<(), Error> {
-    // TODO: Check self subscription
-    let (grouped_updates, course_names, mut group_course_futures) = {
+/// Check the subscriptions chosen by `select_due` among all active ones.
+async fn run_check(
+    mut select_due: impl FnMut(Vec<SubscriptionData>) -> Vec<SubscriptionData>,
+    mut on_new_message: impl FnMut(Notification),
+) -> Result<(), Error> {
+    let (tenant_updates, course_names, mut course_futures) = {
         let conn = CONN.lock().await;
         // TODO: pagination
-        let mut stmt = conn.prepare_cached(
-            "SELECT `u`.`moodle_token`, `g`.`id`, `g`.`course_id`, `g`.`group_qq`, `g`.`user_id`\
+        let mut group_stmt = conn.prepare_cached(
+            "SELECT `u`.`moodle_token`, `g`.`course_id`, `g`.`group_qq`, `g`.`user_id`, `u`.`qq`, \
+            `g`.`failure_count` \
             FROM `user_course_group` AS 'g'\
             INNER JOIN `user` AS 'u' ON `u`.`id` = `g`.`user_id`\
-            WHERE `g`.`failure_count` < 3",
+            WHERE `u`.`token_expired` = 0 AND `u`.`banned` = 0",
         )?;
-        let mut grouped_updates = HashMap::new();
-        let mut course_names = HashMap::new();
-        let group_course_futures: FuturesUnordered<_> = stmt
+        let mut self_stmt = conn.prepare_cached(
+            "SELECT `u`.`moodle_token`, `s`.`course_id`, `s`.`user_id`, `u`.`qq`, \
+            `s`.`failure_count` \
+            FROM `user_course_self` AS 's'\
+            INNER JOIN `user` AS 'u' ON `u`.`id` = `s`.`user_id`\
+            WHERE `u`.`token_expired` = 0 AND `u`.`banned` = 0",
+        )?;
+        let mut telegram_stmt = conn.prepare_cached(
+            "SELECT `u`.`moodle_token`, `t`.`course_id`, `t`.`chat_id`, `t`.`user_id`, \
+            `t`.`failure_count` \
+            FROM `user_course_telegram` AS 't'\
+            INNER JOIN `user` AS 'u' ON `u`.`id` = `t`.`user_id`\
+            WHERE `u`.`token_expired` = 0 AND `u`.`banned` = 0",
+        )?;
+        let mut webhook_stmt = conn.prepare_cached(
+            "SELECT `u`.`moodle_token`, `w`.`course_id`, `w`.`webhook_id`, `w`.`user_id`, \
+            `w`.`failure_count` \
+            FROM `user_course_webhook` AS 'w'\
+            INNER JOIN `user` AS 'u' ON `u`.`id` = `w`.`user_id`\
+            WHERE `u`.`token_expired` = 0 AND `u`.`banned` = 0",
+        )?;
+        let mut email_stmt = conn.prepare_cached(
+            "SELECT `u`.`moodle_token`, `e`.`course_id`, `e`.`user_id`, IFNULL(`u`.`qq`, 0), \
+            `e`.`failure_count` \
+            FROM `user_course_email` AS 'e'\
+            INNER JOIN `user` AS 'u' ON `u`.`id` = `e`.`user_id`\
+            WHERE `u`.`token_expired` = 0 AND `u`.`banned` = 0 \
+            AND `u`.`email` IS NOT NULL",
+        )?;
+        let subscriptions = group_stmt
             .query_map(params![], |row| {
-                let group_qq = row.get(3)?;
-                let updates = grouped_updates
-                    .entry(group_qq)
-                    .or_insert(Arc::new(Mutex::new(Vec::new())))
-                    .clone();
-                let course_id: u32 = row.get(2)?;
+                Ok(SubscriptionData {
+                    token: row.get(0)?,
+                    course_id: row.get(1)?,
+                    tenant: Tenant::Group(row.get(2)?),
+                    user_id: row.get(3)?,
+                    user_qq: row.get(4)?,
+                    failure_count: row.get(5)?,
+                })
+            })?
+            .chain(self_stmt.query_map(params![], |row| {
+                Ok(SubscriptionData {
+                    token: row.get(0)?,
+                    course_id: row.get(1)?,
+                    tenant: Tenant::SenderSelf,
+                    user_id: row.get(2)?,
+                    user_qq: row.get(3)?,
+                    failure_count: row.get(4)?,
+                })
+            })?)
+            .chain(telegram_stmt.query_map(params![], |row| {
+                Ok(SubscriptionData {
+                    token: row.get(0)?,
+                    course_id: row.get(1)?,
+                    tenant: Tenant::Telegram(row.get(2)?),
+                    user_id: row.get(3)?,
+                    user_qq: 0,
+                    failure_count: row.get(4)?,
+                })
+            })?)
+            .chain(webhook_stmt.query_map(params![], |row| {
+                Ok(SubscriptionData {
+                    token: row.get(0)?,
+                    course_id: row.get(1)?,
+                    tenant: Tenant::Webhook(row.get(2)?),
+                    user_id: row.get(3)?,
+                    user_qq: 0,
+                    failure_count: row.get(4)?,
+                })
+            })?)
+            .chain(email_stmt.query_map(params![], |row| {
+                Ok(SubscriptionData {
+                    token: row.get(0)?,
+                    course_id: row.get(1)?,
+                    tenant: Tenant::Email(row.get(2)?),
+                    user_id: row.get(2)?,
+                    user_qq: row.get(3)?,
+                    failure_count: row.get(4)?,
+                })
+            })?)
+            .collect::<Result<Vec<_>, _>>()?;
+        let subscriptions = select_due(subscriptions).into_iter();
+        let mut tenant_updates = HashMap::new();
+        let mut course_names = HashMap::new();
+        let course_futures: FuturesUnordered<_> = subscriptions
+            .map(|subscription| {
+                // Any subscriber identifies the tenant for its templates
+                let (_, updates) = tenant_updates
+                    .entry((subscription.tenant, subscription.user_qq))
+                    .or_insert((subscription.user_id, Arc::new(Mutex::new(Vec::new()))));
+                let updates = updates.clone();
                 let course_name = course_names
-                    .entry(course_id)
+                    .entry(subscription.course_id)
                     .or_insert(Arc::new(Mutex::new(None)))
                     .clone();
-                Ok(check_group_course(
-                    GroupData {
-                        token: row.get(0)?,
-                        group_id: row.get(1)?,
-                        course_id,
-                        group_qq,
-                        user_id: row.get(4)?,
-                    },
-                    updates,
-                    course_name,
-                ))
-            })?
-            .collect::<Result<_, _>>()?;
-        (grouped_updates, course_names, group_course_futures)
+                check_course(subscription, updates, course_name)
+            })
+            .collect();
+        (tenant_updates, course_names, course_futures)
     };
-    while let Some(()) = group_course_futures.next().await {}
-    for (&group_qq, updates) in &grouped_updates {
+    while let Some(()) = course_futures.next().await {}
+    for (&(tenant, user_qq), (user_id, updates)) in &tenant_updates {
         let updates = updates.lock().await;
-        for (course_id, result) in &*updates {
+        if updates.is_empty() {
+            continue;
+        }
+        let language = get_tenant_language(tenant, user_qq).await;
+        let templates = get_templates(tenant, *user_id, language)
+            .await
+            .unwrap_or_else(|e| {
+                log(
+                    LogLevel::Error,
+                    "template",
+                    format!("无法读取 {:?} 的模板：{:#?}", tenant, e),
+                );
+                Templates::new(language)
+            });
+        for (course_id, failure_count, result) in &*updates {
             let course_name = course_names[course_id].try_lock().unwrap();
+            // Not fetched if the check was not made
             let course_name = match course_name.deref() {
-                Some(s) => s,
+                Some(name) => name,
                 None => continue,
             };
             on_new_message(Notification {
-                tenant: Tenant::Group(group_qq),
-                user_qq: 0,
+                tenant,
+                user_qq,
+                course_id: *course_id,
                 course_name: course_name.clone(),
+                failure_count: *failure_count,
                 modules: result,
+                language,
+                templates: &templates,
             });
         }
     }
-    Ok(save_group_updates(
-        grouped_updates
+    save_updates(
+        tenant_updates
             .into_iter()
-            .flat_map(|(_, course_update)| {
+            .flat_map(|(_, (_, course_update))| {
                 replace(&mut *course_update.try_lock().unwrap(), Vec::new())
             })
-            .map(|(_course_id, result)| result)
+            .map(|(_course_id, _failure_count, result)| result)
             .filter(|u| u.is_ok())
             .flat_map(|u| u.unwrap()),
     )
-    .await?)
+    .await
 }
-async fn check_group_course(
-    group_data: GroupData,
-    updates: Arc<Mutex<Vec<(u32, Result<Vec<Update>, Error>)>>>,
-    course_name: Arc<Mutex<Option<String>>>,
+
+async fn check_course(
+    subscription: SubscriptionData,
+    updates: Arc<Mutex<CourseResults>>,
+    course_name: Arc<Mutex<Option<Option<String>>>>,
 ) {
-    let ret = try_check_group_course(&group_data, course_name).await;
+    let ret = try_check_course(&subscription, course_name).await;
+    // An expired token is not a failure of the subscription itself
+    if matches!(&ret, Err(e) if e.is_invalid_token()) {
+        if let Err(e) = handle_expired_token(&subscription).await {
+            log(
+                LogLevel::Error,
+                "token",
+                format!("无法处理过期的 Moodle 登录，{:#?}", e),
+            );
+        }
+        return;
+    }
     let mut updates = updates.lock().await;
     // Increase failure count
     let conn = CONN.lock().await;
-    if let Err(e) = conn.execute(
-        ret.as_ref()
-            .map(|_| {
-                "UPDATE `user_course_group` SET `failure_count` = 0 \
-                WHERE `group_qq` = ?1 AND `course_id` = ?2"
-            })
-            .unwrap_or(
-                "UPDATE `user_course_group` SET `failure_count` = `failure_count` + 1 \
-                WHERE `group_qq` = ?1 AND `course_id` = ?2",
-            ),
-        params![group_data.group_qq, group_data.course_id],
-    ) {
+    let res = match subscription.tenant {
+        Tenant::Group(group_qq) => conn.execute(
+            ret.as_ref()
+                .map(|_| {
+                    "UPDATE `user_course_group` SET `failure_count` = 0 \
+                    WHERE `group_qq` = ?1 AND `course_id` = ?2"
+                })
+                .unwrap_or(
+                    "UPDATE `user_course_group` SET `failure_count` = `failure_count` + 1 \
+                    WHERE `group_qq` = ?1 AND `course_id` = ?2",
+                ),
+            params![group_qq, subscription.course_id],
+        ),
+        Tenant::SenderSelf => conn.execute(
+            ret.as_ref()
+                .map(|_| {
+                    "UPDATE `user_course_self` SET `failure_count` = 0 \
+                    WHERE `user_id` = ?1 AND `course_id` = ?2"
+                })
+                .unwrap_or(
+                    "UPDATE `user_course_self` SET `failure_count` = `failure_count` + 1 \
+                    WHERE `user_id` = ?1 AND `course_id` = ?2",
+                ),
+            params![subscription.user_id, subscription.course_id],
+        ),
+        Tenant::Telegram(chat_id) => conn.execute(
+            ret.as_ref()
+                .map(|_| {
+                    "UPDATE `user_course_telegram` SET `failure_count` = 0 \
+                    WHERE `chat_id` = ?1 AND `course_id` = ?2"
+                })
+                .unwrap_or(
+                    "UPDATE `user_course_telegram` SET `failure_count` = `failure_count` + 1 \
+                    WHERE `chat_id` = ?1 AND `course_id` = ?2",
+                ),
+            params![chat_id, subscription.course_id],
+        ),
+        Tenant::Webhook(webhook_id) => conn.execute(
+            ret.as_ref()
+                .map(|_| {
+                    "UPDATE `user_course_webhook` SET `failure_count` = 0 \
+                    WHERE `webhook_id` = ?1 AND `course_id` = ?2"
+                })
+                .unwrap_or(
+                    "UPDATE `user_course_webhook` SET `failure_count` = `failure_count` + 1 \
+                    WHERE `webhook_id` = ?1 AND `course_id` = ?2",
+                ),
+            params![webhook_id, subscription.course_id],
+        ),
+        Tenant::Email(user_id) => conn.execute(
+            ret.as_ref()
+                .map(|_| {
+                    "UPDATE `user_course_email` SET `failure_count` = 0 \
+                    WHERE `user_id` = ?1 AND `course_id` = ?2"
+                })
+                .unwrap_or(
+                    "UPDATE `user_course_email` SET `failure_count` = `failure_count` + 1 \
+                    WHERE `user_id` = ?1 AND `course_id` = ?2",
+                ),
+            params![user_id, subscription.course_id],
+        ),
+    };
+    if let Err(e) = res {
         dbg!(&e);
-        add_log(
-            CQLogLevel::ERROR,
+        log(
+            LogLevel::Error,
             "inc_fail_cnt",
             format!("无法处理失败次数，{:#?}", e),
-        )
-        .expect("Cannot send cq msg");
+        );
     }
-    updates.push((group_data.course_id, ret));
+    updates.push((subscription.course_id, subscription.failure_count, ret));
 }
 
-async fn try_check_group_course(
-    group_data: &GroupData,
-    course_name: Arc<Mutex<Option<String>>>,
+/// Pause the subscriptions of the token owner, then renew the token with saved
+/// credentials or ask the owner to log in again.
+async fn handle_expired_token(subscription: &SubscriptionData) -> Result<(), Error> {
+    let (credential, (tenant, user_qq)) = {
+        let conn = CONN.lock().await;
+        if !set_token_expired(&conn, subscription.user_id, subscription.token.as_str())? {
+            // Handled by the check of another course already
+            return Ok(());
+        }
+        log(
+            LogLevel::Warning,
+            "token",
+            format!("用户 {} 的 Moodle 登录已过期", subscription.user_id),
+        );
+        (
+            get_user_credential(&conn, subscription.user_id)?,
+            get_user_contact(&conn, subscription.user_id)?,
+        )
+    };
+    let language = get_tenant_language(tenant, user_qq).await;
+    let msg = match credential {
+        Some((campus_id, password)) => match login_moodle(campus_id, password).await {
+            Ok(token) => {
+                save_user_token(&*CONN.lock().await, subscription.user_id, token.as_str())?;
+                return Ok(());
+            }
+            Err(e) => trf(language, "token.renew_failed", &[&e.localize(language)]),
+        },
+        None => tr(language, "token.expired").to_string(),
+    };
+    send_to_tenant(tenant, user_qq, msg.as_str());
+    Ok(())
+}
+
+async fn try_check_course(
+    subscription: &SubscriptionData,
+    course_name: Arc<Mutex<Option<Option<String>>>>,
 ) -> Result<Vec<Update>, Error> {
-    let course_id = group_data.course_id;
-    // Try to get the course name
+    let course_id = subscription.course_id;
+    // Try to get the course name, `Some(None)` if the course cannot be found
     if let Some(mut c) = course_name.try_lock() {
         if c.is_none() {
             // Get course name
-            match get_course_public_information(group_data.token.as_str(), course_id).await {
+            match get_course_public_information(subscription.token.as_str(), course_id).await {
                 Ok(mut info) => {
-                    *c = Some(
-                        info.courses
-                            .pop()
-                            .map(|c| c.full_name)
-                            .unwrap_or(format!("未知课程 {}", course_id)),
-                    );
+                    *c = Some(match info.courses.pop() {
+                        Some(course) => {
+                            if let Err(e) =
+                                save_course_name(course_id, course.full_name.as_str()).await
+                            {
+                                log(
+                                    LogLevel::Error,
+                                    "course_name",
+                                    format!("无法保存课程名称 {:#?}", e),
+                                );
+                            }
+                            Some(course.full_name)
+                        }
+                        None => None,
+                    });
                 }
                 Err(e) => {
                     // Must assign a course name otherwise error notification
                     // will be muted
-                    *c = Some(format!("未知课程 {}", course_id));
-                    add_log(
-                        CQLogLevel::ERROR,
+                    *c = Some(None);
+                    log(
+                        LogLevel::Error,
                         "course_name",
                         format!("获取课程名称错误 {:#?}", e),
-                    )
-                    .expect("Cannot send course_name error message to cq");
+                    );
                 }
             }
         }
@@ -303,37 +863,146 @@ async fn try_check_group_course(
     // the course name
 
     // Get course modules to check updates
-    let modules = get_course_content(group_data.token.as_str(), course_id)
+    let modules: Vec<_> = get_course_content(subscription.token.as_str(), course_id)
         .await?
         .into_iter()
-        .flat_map(|s| s.modules);
+        .flat_map(|s| s.modules)
+        .collect();
     let conn = CONN.lock().await;
-    let module_records: HashMap<u32, (u32, NaiveDateTime)> = conn
+    let mut module_records: HashMap<u32, ModuleRecord> = conn
         .prepare_cached(
-            "SELECT `id`, `module_id`, `updated_at` FROM `user_course_module`\
-                WHERE `user_id` = ?1 AND `course_id` = ?2",
+            "SELECT `id`, `module_id`, `last_modified`, `visible`, `module_name`, `removed`, \
+            `content_hash` FROM `user_course_module` WHERE `user_id` = ?1 AND `course_id` = ?2",
         )?
-        .query_map(params![group_data.user_id, course_id], |row| {
-            Ok((row.get(1)?, (row.get(0)?, row.get(2)?)))
+        .query_map(params![subscription.user_id, course_id], |row| {
+            Ok((
+                row.get(1)?,
+                ModuleRecord {
+                    id: row.get(0)?,
+                    last_modified: row.get(2)?,
+                    visible: row.get(3)?,
+                    name: row.get(4)?,
+                    removed: row.get(5)?,
+                    content_hash: row.get(6)?,
+                },
+            ))
         })?
         .collect::<Result<_, _>>()?;
-    Ok(modules
-        // TODO: check the last update timestamp from response
-        .filter(|m| !module_records.contains_key(&m.id))
-        .map(|m| Update {
-            update_type: if module_records.contains_key(&m.id) {
-                UpdateType::Update(m.id)
-            } else {
-                UpdateType::Insert
-            },
-            user_id: group_data.user_id,
+    let mut baseline_stmt = conn.prepare_cached(
+        "UPDATE `user_course_module` SET `last_modified` = ?1, `visible` = ?2, \
+        `module_name` = ?3, `content_hash` = ?4, `removed` = 0 WHERE `id` = ?5",
+    )?;
+    // An empty course is more likely a glitch than everything being removed
+    let check_removal = !modules.is_empty();
+    let mut updates = Vec::new();
+    for module in modules {
+        let last_modified = module.content.last_modified();
+        let content_hash = module.content.content_hash();
+        // Records without a hash yet only compare the timestamp
+        let is_modified = |record: &ModuleRecord| {
+            matches!(record.last_modified, Some(r) if last_modified > r)
+                || match (&record.content_hash, &content_hash) {
+                    (Some(recorded), Some(hash)) => recorded != hash,
+                    _ => false,
+                }
+        };
+        let update_type = match module_records.remove(&module.id) {
+            None => UpdateType::Insert,
+            // Modules hidden from students may disappear from the response
+            // instead of turning invisible
+            Some(record) if record.removed && module.user_visible => UpdateType::Show(record.id),
+            Some(record) if !record.removed && record.visible == Some(!module.user_visible) => {
+                if module.user_visible {
+                    UpdateType::Show(record.id)
+                } else {
+                    UpdateType::Hide(record.id)
+                }
+            }
+            Some(record) if !record.removed && is_modified(&record) => {
+                UpdateType::Update(record.id)
+            }
+            Some(record) => {
+                // Records created before these fields were tracked, or with
+                // files removed, are brought up to date silently
+                if record.last_modified != Some(last_modified)
+                    || record.content_hash != content_hash
+                    || record.visible != Some(module.user_visible)
+                    || record.name.as_ref() != Some(&module.name)
+                    || record.removed
+                {
+                    baseline_stmt.execute(params![
+                        last_modified,
+                        module.user_visible,
+                        module.name,
+                        content_hash,
+                        record.id
+                    ])?;
+                }
+                continue;
+            }
+        };
+        updates.push(Update {
+            update_type,
+            user_id: subscription.user_id,
             course_id,
-            module: m,
-        })
-        .collect())
+            module,
+        });
+    }
+    if check_removal {
+        for (module_id, record) in module_records {
+            if record.removed {
+                continue;
+            }
+            updates.push(Update {
+                update_type: UpdateType::Remove(record.id),
+                user_id: subscription.user_id,
+                course_id,
+                module: CourseModule {
+                    id: module_id,
+                    name: record.name.unwrap_or_default(),
+                    user_visible: false,
+                    content: ModuleType::Other,
+                },
+            });
+        }
+    }
+    Ok(updates)
 }
 
 #[tokio::test]
 async fn run_check_test() {
-    run_check(|u| println!("{:#?}", u)).await.unwrap();
+    run_check(|s| s, |u| println!("{:#?}", u)).await.unwrap();
+}
+
+#[test]
+fn format_updates_test() {
+    let update = |update_type, name: &str| Update {
+        update_type,
+        user_id: 1,
+        course_id: 1,
+        module: CourseModule {
+            id: 1,
+            name: name.to_string(),
+            user_visible: true,
+            content: ModuleType::Page { contents: None },
+        },
+    };
+    assert_eq!(
+        format_updates(
+            "课程",
+            1,
+            &[
+                update(UpdateType::Update(1), "讲义"),
+                update(UpdateType::Hide(2), "答案"),
+                update(UpdateType::Remove(3), "旧讲义"),
+                update(UpdateType::Remove(4), "旧答案"),
+            ],
+            &Templates::default()
+        ),
+        vec![
+            "课程 更新了一个页面 讲义，快去看看吧",
+            "课程 的页面 答案 又被隐藏了",
+            "课程 移除了 2 个内容",
+        ]
+    );
 }
@@ -0,0 +1,265 @@
+use crate::locale::{tr, trf, Language};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommandKind {
+    Help,
+    Login,
+    Subscribe,
+    SubscribeAll,
+    Select,
+    Unsubscribe,
+    List,
+    Resume,
+    Email,
+    Policy,
+    Template,
+    Language,
+    Log,
+    Week,
+    Stats,
+    Users,
+    Groups,
+    ResetFailures,
+    Broadcast,
+    Ban,
+}
+
+/// A chat command. What the "帮助" command shows about it is looked up in the
+/// message catalog by the first alias.
+#[derive(Debug)]
+pub struct Command {
+    pub kind: CommandKind,
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+}
+
+pub static COMMANDS: &[Command] = &[
+    Command {
+        kind: CommandKind::Help,
+        name: "帮助",
+        aliases: &["help", "start"],
+    },
+    Command {
+        kind: CommandKind::Login,
+        name: "登录",
+        aliases: &["login"],
+    },
+    Command {
+        kind: CommandKind::Subscribe,
+        name: "订阅",
+        aliases: &["subscribe"],
+    },
+    Command {
+        kind: CommandKind::SubscribeAll,
+        name: "订阅全部",
+        aliases: &["subscribeall"],
+    },
+    Command {
+        kind: CommandKind::Select,
+        name: "选择",
+        aliases: &["choose"],
+    },
+    Command {
+        kind: CommandKind::Unsubscribe,
+        name: "退订",
+        aliases: &["unsubscribe"],
+    },
+    Command {
+        kind: CommandKind::List,
+        name: "列表",
+        aliases: &["list"],
+    },
+    Command {
+        kind: CommandKind::Resume,
+        name: "恢复",
+        aliases: &["resume"],
+    },
+    Command {
+        kind: CommandKind::Email,
+        name: "邮箱",
+        aliases: &["email"],
+    },
+    Command {
+        kind: CommandKind::Policy,
+        name: "权限",
+        aliases: &["policy"],
+    },
+    Command {
+        kind: CommandKind::Template,
+        name: "模板",
+        aliases: &["template"],
+    },
+    Command {
+        kind: CommandKind::Language,
+        name: "语言",
+        aliases: &["language"],
+    },
+    Command {
+        kind: CommandKind::Log,
+        name: "记录",
+        aliases: &["log"],
+    },
+    Command {
+        kind: CommandKind::Week,
+        name: "这周有什么",
+        aliases: &["week"],
+    },
+];
+
+/// Commands of the admin console, only available to superusers in private
+pub static ADMIN_COMMANDS: &[Command] = &[
+    Command {
+        kind: CommandKind::Stats,
+        name: "统计",
+        aliases: &["stats"],
+    },
+    Command {
+        kind: CommandKind::Users,
+        name: "用户",
+        aliases: &["users"],
+    },
+    Command {
+        kind: CommandKind::Groups,
+        name: "群",
+        aliases: &["groups"],
+    },
+    Command {
+        kind: CommandKind::ResetFailures,
+        name: "重置失败",
+        aliases: &["reset-failures"],
+    },
+    Command {
+        kind: CommandKind::Broadcast,
+        name: "广播",
+        aliases: &["broadcast"],
+    },
+    Command {
+        kind: CommandKind::Ban,
+        name: "封禁",
+        aliases: &["ban"],
+    },
+];
+
+impl Command {
+    pub fn is_admin(&self) -> bool {
+        ADMIN_COMMANDS.iter().any(|c| c.kind == self.kind)
+    }
+
+    /// Name shown in `language`, the first alias for English
+    pub fn display_name(&self, language: Language) -> &'static str {
+        match language {
+            Language::Chinese => self.name,
+            Language::English => self.aliases[0],
+        }
+    }
+
+    /// Arguments after the name, optional ones in brackets
+    pub fn args(&self, language: Language) -> &'static str {
+        tr(
+            language,
+            format!("command.{}.args", self.aliases[0]).as_str(),
+        )
+    }
+
+    pub fn description(&self, language: Language) -> &'static str {
+        tr(
+            language,
+            format!("command.{}.description", self.aliases[0]).as_str(),
+        )
+    }
+
+    pub fn usage(&self, language: Language) -> String {
+        let name = self.display_name(language);
+        let usage = match self.args(language) {
+            "" => name.to_string(),
+            args => format!("{} {}", name, args),
+        };
+        trf(language, "help.usage", &[&usage])
+    }
+}
+
+/// Look up a command by its name or an alias. A leading "/" and a trailing
+/// "@bot" as in Telegram commands are ignored.
+pub fn find_command(word: &str) -> Option<&'static Command> {
+    let word = word.trim_start_matches('/');
+    let word = word.split('@').next().unwrap_or(word);
+    COMMANDS.iter().chain(ADMIN_COMMANDS).find(|c| {
+        c.name == word
+            || c.aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(word))
+    })
+}
+
+/// List of all commands, or the usage of one command. Admin commands are only
+/// shown with `show_admin`.
+pub fn help(command: Option<&str>, show_admin: bool, language: Language) -> String {
+    match command {
+        Some(word) => match find_command(word).filter(|c| show_admin || !c.is_admin()) {
+            Some(c) => {
+                let mut msg = format!("{}\n{}", c.description(language), c.usage(language));
+                let display_name = c.display_name(language);
+                let others: Vec<_> = std::iter::once(&c.name)
+                    .chain(c.aliases)
+                    .filter(|&&n| n != display_name)
+                    .cloned()
+                    .collect();
+                if !others.is_empty() {
+                    let others = others.join(tr(language, "list.separator"));
+                    msg.push_str(trf(language, "help.aliases", &[&others]).as_str());
+                }
+                msg
+            }
+            None => trf(language, "help.unknown", &[&word]),
+        },
+        None => {
+            let mut msg = tr(language, "help.title").to_string();
+            let line =
+                |c: &Command| format!("\n{} {}", c.display_name(language), c.description(language));
+            for c in COMMANDS {
+                msg.push_str(line(c).as_str());
+            }
+            if show_admin {
+                msg.push_str(tr(language, "help.admin"));
+                for c in ADMIN_COMMANDS {
+                    msg.push_str(line(c).as_str());
+                }
+            }
+            msg.push_str(tr(language, "help.hint"));
+            msg
+        }
+    }
+}
+
+#[test]
+fn find_command_test() {
+    assert_eq!(find_command("订阅").unwrap().kind, CommandKind::Subscribe);
+    assert_eq!(
+        find_command("Unsubscribe").unwrap().kind,
+        CommandKind::Unsubscribe
+    );
+    assert_eq!(
+        find_command("/list@moodle_bot").unwrap().kind,
+        CommandKind::List
+    );
+    assert!(find_command("订").is_none());
+    assert_eq!(
+        help(Some("week"), false, Language::Chinese),
+        "列出已订阅课程未来 7 天的日程\n用法：这周有什么\n也可以用 week"
+    );
+    assert_eq!(
+        help(Some("这周有什么"), false, Language::English),
+        "List the events of the subscribed courses in the coming 7 days\n\
+        Usage: week\nAlso: 这周有什么"
+    );
+    for c in COMMANDS.iter().chain(ADMIN_COMMANDS) {
+        for &language in crate::locale::LANGUAGES {
+            assert!(!c.description(language).is_empty());
+        }
+    }
+    assert_eq!(
+        find_command("/reset-failures").unwrap().kind,
+        CommandKind::ResetFailures
+    );
+    assert!(help(Some("ban"), false, Language::Chinese).starts_with("没有"));
+}
@@ -0,0 +1,49 @@
+use crate::assignment::AssignmentConfig;
+use crate::calendar::CalendarConfig;
+use crate::forum::ForumConfig;
+use crate::locale::Language;
+use crate::messenger::{EmailConfig, OneBotConfig, TelegramConfig, WebhookConfig};
+use crate::permission::PermissionConfig;
+use crate::schedule::Schedule;
+use crate::DATA_PATH;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Chat backend used by the standalone binary
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    OneBot,
+}
+
+/// Bot settings read from `config.json` in the data directory. Missing
+/// fields take their default values.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub backend: Backend,
+    /// Language of the users and chats that have not chosen one
+    pub language: Language,
+    pub schedule: Schedule,
+    pub assignment: AssignmentConfig,
+    pub forum: ForumConfig,
+    pub calendar: CalendarConfig,
+    pub onebot: OneBotConfig,
+    pub telegram: TelegramConfig,
+    /// Webhooks that courses can be subscribed to, keyed by ID
+    pub webhooks: HashMap<i64, WebhookConfig>,
+    pub email: EmailConfig,
+    pub permission: PermissionConfig,
+}
+
+lazy_static! {
+    pub static ref CONFIG: Config = {
+        let path = DATA_PATH.join("config.json");
+        match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).expect("Cannot parse config file"),
+            Err(_) => Config::default(),
+        }
+    };
+}
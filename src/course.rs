@@ -0,0 +1,177 @@
+use crate::error::Error;
+use crate::locale::{tr, Language};
+use crate::messenger::{log, LogLevel};
+use crate::moodle::{
+    get_course_public_information, get_site_info, get_user_courses, search_courses, CourseSummary,
+};
+use crate::user::get_user_moodle_token;
+use crate::CONN;
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+
+pub async fn save_course_name(course_id: u32, name: &str) -> Result<(), Error> {
+    CONN.lock().await.execute(
+        "INSERT OR REPLACE INTO `course` (`course_id`, `name`, `updated_at`) VALUES (?1, ?2, ?3)",
+        params![course_id, name, Utc::now().naive_utc()],
+    )?;
+    Ok(())
+}
+
+pub async fn get_cached_course_name(course_id: u32) -> Result<Option<String>, Error> {
+    Ok(CONN
+        .lock()
+        .await
+        .query_row(
+            "SELECT `name` FROM `course` WHERE `course_id` = ?1",
+            params![course_id],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+/// The course name, or "未知课程" in `language` if it is unknown.
+pub fn course_display_name(name: Option<&str>, language: Language) -> &str {
+    name.unwrap_or_else(|| tr(language, "course.unknown"))
+}
+
+/// Get the course name from cache, or from Moodle if it is not cached yet.
+/// Returns `None` if the course cannot be found.
+pub async fn get_course_name(token: &str, course_id: u32) -> Option<String> {
+    if let Ok(Some(name)) = get_cached_course_name(course_id).await {
+        return Some(name);
+    }
+    match get_course_public_information(token, course_id).await {
+        Ok(mut info) => match info.courses.pop() {
+            Some(c) => {
+                if let Err(e) = save_course_name(course_id, c.full_name.as_str()).await {
+                    log(
+                        LogLevel::Error,
+                        "course_name",
+                        format!("无法保存课程名称 {:#?}", e),
+                    );
+                }
+                Some(c.full_name)
+            }
+            None => None,
+        },
+        Err(e) => {
+            log(
+                LogLevel::Error,
+                "course_name",
+                format!("获取课程名称错误 {:#?}", e),
+            );
+            None
+        }
+    }
+}
+
+/// Courses whose full name or short name contains `query`, ignoring case. An
+/// exact match is returned alone.
+fn match_courses(courses: Vec<CourseSummary>, query: &str) -> Vec<CourseSummary> {
+    let query = query.to_lowercase();
+    let is_exact = |c: &CourseSummary| {
+        c.short_name.to_lowercase() == query || c.full_name.to_lowercase() == query
+    };
+    if courses.iter().filter(|c| is_exact(c)).count() == 1 {
+        return courses.into_iter().filter(is_exact).collect();
+    }
+    courses
+        .into_iter()
+        .filter(|c| {
+            c.short_name.to_lowercase().contains(query.as_str())
+                || c.full_name.to_lowercase().contains(query.as_str())
+        })
+        .collect()
+}
+
+async fn save_course_names(courses: &[CourseSummary]) {
+    for course in courses {
+        if let Err(e) = save_course_name(course.id, course.full_name.as_str()).await {
+            log(
+                LogLevel::Error,
+                "course_name",
+                format!("无法保存课程名称 {:#?}", e),
+            );
+        }
+    }
+}
+
+async fn get_enrolled_courses(token: &str) -> Result<Vec<CourseSummary>, Error> {
+    let site_info = get_site_info(token).await?;
+    Ok(get_user_courses(token, site_info.user_id).await?)
+}
+
+/// Find courses by name or short code with the Moodle token of a user. The
+/// courses the user is enrolled in are tried before searching the whole site.
+pub async fn find_courses(user_id: u32, query: &str) -> Result<Vec<CourseSummary>, Error> {
+    let token = get_user_moodle_token(&*CONN.lock().await, user_id)?;
+    let mut courses = match_courses(get_enrolled_courses(token.as_str()).await?, query);
+    if courses.is_empty() {
+        courses = search_courses(token.as_str(), query).await?.courses;
+    }
+    save_course_names(courses.as_slice()).await;
+    Ok(courses)
+}
+
+/// Whether a course runs at `now`. Courses starting within a month count as
+/// well, so the next semester can be subscribed in advance.
+fn is_current_course(course: &CourseSummary, now: i64) -> bool {
+    (course.start_date == 0 || course.start_date <= now + 60 * 60 * 24 * 30)
+        && (course.end_date == 0 || course.end_date >= now)
+}
+
+/// Courses of this semester the user is enrolled in
+pub async fn get_current_courses(user_id: u32) -> Result<Vec<CourseSummary>, Error> {
+    let token = get_user_moodle_token(&*CONN.lock().await, user_id)?;
+    let now = Utc::now().timestamp();
+    let courses: Vec<_> = get_enrolled_courses(token.as_str())
+        .await?
+        .into_iter()
+        .filter(|c| is_current_course(c, now))
+        .collect();
+    save_course_names(courses.as_slice()).await;
+    Ok(courses)
+}
+
+#[test]
+fn match_courses_test() {
+    let course = |id, short_name: &str, full_name: &str| CourseSummary {
+        id,
+        short_name: short_name.to_string(),
+        full_name: full_name.to_string(),
+        start_date: 0,
+        end_date: 0,
+    };
+    let courses = vec![
+        course(1, "MAT101", "Calculus I"),
+        course(2, "MAT102", "Calculus II"),
+        course(3, "CS101", "Programming"),
+    ];
+    let ids = |query| {
+        match_courses(courses.clone(), query)
+            .iter()
+            .map(|c| c.id)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ids("calculus"), vec![1, 2]);
+    assert_eq!(ids("calculus i"), vec![1]);
+    assert_eq!(ids("cs101"), vec![3]);
+    assert_eq!(ids("physics"), Vec::<u32>::new());
+}
+
+#[test]
+fn is_current_course_test() {
+    let now = 1_600_000_000;
+    let course = |start_date, end_date| CourseSummary {
+        id: 1,
+        short_name: String::new(),
+        full_name: String::new(),
+        start_date,
+        end_date,
+    };
+    assert!(is_current_course(&course(0, 0), now));
+    assert!(is_current_course(&course(now - 100, now + 100), now));
+    assert!(is_current_course(&course(now + 100, 0), now));
+    assert!(!is_current_course(&course(now - 200, now - 100), now));
+    assert!(!is_current_course(&course(now + 60 * 60 * 24 * 60, 0), now));
+}
@@ -0,0 +1,76 @@
+use crate::error::Error;
+use crate::DATA_PATH;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::Aes256Gcm;
+use lazy_static::lazy_static;
+use rand::Rng;
+use std::convert::TryInto;
+use zeroize::Zeroize;
+
+const NONCE_LEN: usize = 12;
+
+lazy_static! {
+    // The key is kept apart from the database so that a leaked database alone
+    // does not reveal any password
+    static ref CIPHER: Aes256Gcm = {
+        let path = DATA_PATH.join("secret.key");
+        let key: [u8; 32] = match std::fs::read(&path) {
+            Ok(key) => key.as_slice().try_into().expect("Invalid secret key"),
+            Err(_) => {
+                std::fs::create_dir_all(&*DATA_PATH).expect("Cannot create data dir");
+                let key: [u8; 32] = rand::thread_rng().gen();
+                std::fs::write(&path, key).expect("Cannot write secret key");
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt;
+                    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+                        .expect("Cannot restrict secret key permissions");
+                }
+                key
+            }
+        };
+        Aes256Gcm::new(&key.into())
+    };
+}
+
+/// Encrypt Moodle credentials to be saved in the `user` table.
+pub fn encrypt_credential(campus_id: &str, password: &str) -> Result<Vec<u8>, Error> {
+    let nonce: [u8; NONCE_LEN] = rand::thread_rng().gen();
+    let mut plaintext = format!("{}\n{}", campus_id, password);
+    let ciphertext = CIPHER.encrypt(&nonce.into(), plaintext.as_bytes());
+    plaintext.zeroize();
+    let ciphertext = ciphertext.map_err(|_| Error::Localized("error.encrypt", vec![]))?;
+    Ok(nonce.iter().copied().chain(ciphertext).collect())
+}
+
+/// Decrypt saved Moodle credentials into campus ID and password.
+pub fn decrypt_credential(credential: &[u8]) -> Result<(String, String), Error> {
+    let invalid = || Error::Localized("error.decrypt", vec![]);
+    if credential.len() < NONCE_LEN {
+        return Err(invalid());
+    }
+    let (nonce, ciphertext) = credential.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().map_err(|_| invalid())?;
+    let mut plaintext = String::from_utf8(
+        CIPHER
+            .decrypt(&nonce.into(), ciphertext)
+            .map_err(|_| invalid())?,
+    )
+    .map_err(|_| invalid())?;
+    let ret = match plaintext.find('\n') {
+        Some(i) => Ok((plaintext[..i].to_string(), plaintext[i + 1..].to_string())),
+        None => Err(invalid()),
+    };
+    plaintext.zeroize();
+    ret
+}
+
+#[test]
+fn credential_test() {
+    let credential = encrypt_credential("SWE1909000", "p@ssw0rd").unwrap();
+    assert_eq!(
+        decrypt_credential(credential.as_slice()).unwrap(),
+        ("SWE1909000".to_string(), "p@ssw0rd".to_string())
+    );
+    assert!(decrypt_credential(&credential[1..]).is_err());
+}
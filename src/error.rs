@@ -1,3 +1,5 @@
+use crate::config::CONFIG;
+use crate::locale::{tr, trf, Language};
 use crate::moodle;
 use std::fmt::{self, Display, Formatter};
 
@@ -7,35 +9,54 @@ pub enum Error {
     NotExist,
     Moodle(moodle::Error),
     Db(rusqlite::Error),
-    Other(String),
+    /// A message in the catalog and its arguments
+    Localized(&'static str, Vec<String>),
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.localize(CONFIG.language).as_str())
+    }
+}
+
+impl Error {
+    /// Message of the error in `language`
+    pub fn localize(&self, language: Language) -> String {
         match self {
-            Error::Duplicated => f.write_str("记录重复了，请删除再试"),
-            Error::NotExist => f.write_str("记录不存在呢"),
-            Error::Other(s) => f.write_str(s.as_str()),
-            Error::Db(d) => {
-                f.write_str("数据库出错了")?;
-                d.fmt(f)
+            Error::Duplicated => tr(language, "error.duplicated").to_string(),
+            Error::NotExist => tr(language, "error.not_exist").to_string(),
+            Error::Localized(key, args) => {
+                let args: Vec<&dyn Display> = args.iter().map(|a| a as &dyn Display).collect();
+                trf(language, key, args.as_slice())
             }
+            Error::Db(d) => with_cause(language, "error.db", d),
             Error::Moodle(m) => match m {
-                moodle::Error::Req(e) => {
-                    f.write_str("和 Moodle 通讯时出错了")?;
-                    e.fmt(f)
-                }
+                moodle::Error::Req(e) => with_cause(language, "error.moodle_request", e),
                 moodle::Error::Moodle(me) => {
                     if me.error_code == "invalidtoken" {
-                        f.write_str("Moodle 登录过期了")
+                        tr(language, "error.invalid_token").to_string()
+                    } else if me.error_code == "invalidlogin" {
+                        tr(language, "error.invalid_login").to_string()
                     } else {
-                        f.write_str("Moodle 出错了")?;
-                        me.fmt(f)
+                        with_cause(language, "error.moodle", me)
                     }
                 }
             },
         }
     }
+
+    /// Whether the Moodle token is no longer accepted
+    pub fn is_invalid_token(&self) -> bool {
+        matches!(self, Error::Moodle(m) if m.is_invalid_token())
+    }
+}
+
+/// A message in the catalog followed by the details of its cause
+fn with_cause(language: Language, key: &str, cause: &dyn Display) -> String {
+    match language {
+        Language::Chinese => format!("{}：{}", tr(language, key), cause),
+        Language::English => format!("{}: {}", tr(language, key), cause),
+    }
 }
 
 impl std::error::Error for Error {}
@@ -55,3 +76,20 @@ impl From<rusqlite::Error> for Error {
         }
     }
 }
+
+#[test]
+fn localize_test() {
+    let error = Error::Localized("error.no_email", vec!["1".to_string()]);
+    assert_eq!(error.localize(Language::Chinese), "用户 1 没有绑定邮箱");
+    assert_eq!(
+        error.localize(Language::English),
+        "User 1 has not linked an email address"
+    );
+    let error = Error::Db(rusqlite::Error::InvalidQuery);
+    assert!(error
+        .localize(Language::Chinese)
+        .starts_with("数据库出错了："));
+    assert!(error
+        .localize(Language::English)
+        .starts_with("Database error: "));
+}
@@ -0,0 +1,229 @@
+use crate::check::{deliver_to_course_tenants, TICK};
+use crate::config::CONFIG;
+use crate::course::{course_display_name, get_course_name};
+use crate::error::Error;
+use crate::locale::{trf, Language, Localized};
+use crate::messenger::{log, LogLevel};
+use crate::moodle::{get_forum_discussions, get_forums, Discussion, Forum};
+use crate::subscribe::get_token_courses;
+use crate::CONN;
+use chrono::Utc;
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use tokio::time::{delay_for, Duration, Instant};
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ForumConfig {
+    /// Interval in seconds between two checks of forum discussions
+    pub interval: u64,
+    /// Maximum number of characters quoted from a post
+    pub excerpt_length: usize,
+}
+
+impl Default for ForumConfig {
+    fn default() -> Self {
+        ForumConfig {
+            interval: 60 * 10,
+            excerpt_length: 100,
+        }
+    }
+}
+
+pub async fn start_forum_loop() {
+    // Avoid msg spam at startup
+    if let Err(e) = check_forums().await {
+        log(
+            LogLevel::Error,
+            "forum",
+            format!("初始论坛更新检查失败：{:#?}", e),
+        );
+    }
+    let mut last_check = Instant::now();
+    loop {
+        delay_for(TICK).await;
+        if last_check.elapsed() < Duration::from_secs(CONFIG.forum.interval) {
+            continue;
+        }
+        last_check = Instant::now();
+        match check_forums().await {
+            Ok(msgs) => deliver_to_course_tenants(msgs).await,
+            Err(e) => {
+                log(
+                    LogLevel::Error,
+                    "forum",
+                    format!("无法检查论坛更新，{:#?}", e),
+                );
+            }
+        }
+    }
+}
+
+/// Plain text of the beginning of a HTML post.
+fn excerpt(html: &str, max_chars: usize) -> String {
+    let mut text = String::new();
+    let mut tag: Option<String> = None;
+    for c in html.chars() {
+        match (&mut tag, c) {
+            (None, '<') => tag = Some(String::new()),
+            (Some(t), '>') => {
+                // Block elements separate words
+                let name = t
+                    .trim_start_matches('/')
+                    .split(|c: char| c.is_whitespace() || c == '/')
+                    .next()
+                    .unwrap_or("")
+                    .to_ascii_lowercase();
+                if matches!(name.as_str(), "p" | "br" | "div" | "li" | "tr" | "td") {
+                    text.push(' ');
+                }
+                tag = None;
+            }
+            (Some(t), c) => t.push(c),
+            (None, c) => text.push(c),
+        }
+    }
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&");
+    let words: Vec<_> = text.split_whitespace().collect();
+    let text = words.join(" ");
+    if text.chars().count() > max_chars {
+        text.chars().take(max_chars).collect::<String>() + "…"
+    } else {
+        text
+    }
+}
+
+fn format_discussion(
+    course_name: Option<&str>,
+    forum: &Forum,
+    discussion: &Discussion,
+    language: Language,
+) -> String {
+    trf(
+        language,
+        "forum.new",
+        &[
+            &course_display_name(course_name, language),
+            &forum.name,
+            &discussion.subject,
+            &discussion.author,
+            &excerpt(discussion.message.as_str(), CONFIG.forum.excerpt_length),
+        ],
+    )
+}
+
+/// Save the discussions of all subscribed courses. Returns notifications
+/// about new discussions. Discussions of forums seen for the first time are
+/// saved silently.
+async fn check_forums() -> Result<Vec<(u32, Localized)>, Error> {
+    let mut msgs = Vec::new();
+    for (token, course_ids) in get_token_courses().await? {
+        let forums = match get_forums(&token, course_ids.as_slice()).await {
+            Ok(f) => f,
+            Err(e) => {
+                log(
+                    LogLevel::Error,
+                    "forum",
+                    format!("获取课程 {:?} 的论坛失败 {:#?}", course_ids, e),
+                );
+                continue;
+            }
+        };
+        let mut course_names = HashMap::new();
+        for forum in forums {
+            let discussions = match get_forum_discussions(&token, forum.id).await {
+                Ok(d) => d.discussions,
+                Err(e) => {
+                    log(
+                        LogLevel::Error,
+                        "forum",
+                        format!("获取论坛 {} 的帖子失败 {:#?}", forum.id, e),
+                    );
+                    continue;
+                }
+            };
+            let new_discussions = save_discussions(&forum, discussions).await?;
+            if new_discussions.is_empty() {
+                continue;
+            }
+            if let Entry::Vacant(entry) = course_names.entry(forum.course_id) {
+                entry.insert(get_course_name(token.as_str(), forum.course_id).await);
+            }
+            let course_name = course_names[&forum.course_id].as_deref();
+            msgs.extend(new_discussions.iter().map(|d| {
+                (
+                    forum.course_id,
+                    Localized::new(|language| format_discussion(course_name, &forum, d, language)),
+                )
+            }));
+        }
+    }
+    Ok(msgs)
+}
+
+/// Save unseen discussions and return the ones to announce.
+async fn save_discussions(
+    forum: &Forum,
+    discussions: Vec<Discussion>,
+) -> Result<Vec<Discussion>, Error> {
+    let mut conn = CONN.lock().await;
+    let tx = conn.transaction()?;
+    let now = Utc::now().naive_utc();
+    let known_forum = tx
+        .query_row(
+            "SELECT `id` FROM `forum` WHERE `forum_id` = ?1",
+            params![forum.id],
+            |row| row.get::<_, u32>(0),
+        )
+        .optional()?
+        .is_some();
+    if !known_forum {
+        tx.execute(
+            "INSERT INTO `forum` (`forum_id`, `course_id`, `created_at`) VALUES (?1, ?2, ?3)",
+            params![forum.id, forum.course_id, now],
+        )?;
+    }
+    let mut new_discussions = Vec::new();
+    {
+        let mut select_stmt =
+            tx.prepare_cached("SELECT `id` FROM `forum_discussion` WHERE `discussion_id` = ?1")?;
+        let mut insert_stmt = tx.prepare_cached(
+            "INSERT INTO `forum_discussion` (`discussion_id`, `forum_id`, `created_at`) \
+            VALUES (?1, ?2, ?3)",
+        )?;
+        for discussion in discussions {
+            let exists = select_stmt
+                .query_row(params![discussion.id], |row| row.get::<_, u32>(0))
+                .optional()?
+                .is_some();
+            if exists {
+                continue;
+            }
+            insert_stmt.execute(params![discussion.id, forum.id, now])?;
+            if known_forum {
+                new_discussions.push(discussion);
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(new_discussions)
+}
+
+#[test]
+fn excerpt_test() {
+    assert_eq!(
+        excerpt(
+            "<p>Dear all,</p><p>Quiz&nbsp;1 is <b>postponed</b>.</p>",
+            100
+        ),
+        "Dear all, Quiz 1 is postponed."
+    );
+    assert_eq!(excerpt("<p>一二三四五六</p>", 3), "一二三…");
+}
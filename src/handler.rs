@@ -0,0 +1,1124 @@
+use crate::admin::run_admin_command;
+use crate::calendar::list_upcoming_events;
+use crate::check::format_time;
+use crate::command::{find_command, help, Command, CommandKind};
+use crate::config::CONFIG;
+use crate::course::{course_display_name, find_courses, get_current_courses};
+use crate::error::Error;
+use crate::locale::{
+    get_chat_language, get_reply_language, get_user_language, set_chat_language, set_user_language,
+    tr, trf, Language, LANGUAGES,
+};
+use crate::messenger::{log, send_confirmation, send_to_tenant, LogLevel};
+use crate::moodle::CourseSummary;
+use crate::permission::{
+    get_group_policy, is_superuser, list_audit, record_audit, set_group_policy, AuditAction,
+    AuditEntry, GroupPolicy, Role,
+};
+use crate::subscribe::{
+    add_subscribe, get_tenant_courses, list_tenant_subscriptions, remove_chat_subscribe,
+    remove_group_subscribe, remove_subscribe, remove_telegram_subscribe, reset_failures,
+    SubscriptionInfo,
+};
+use crate::template::{
+    get_templates, set_template, Template, TemplateKind, Templates, TEMPLATE_KINDS, VARIABLES,
+};
+use crate::tenant::Tenant;
+use crate::user::{get_user_email, get_user_id, is_banned, login_user, set_user_email, Account};
+use chrono::{TimeZone, Utc};
+use lazy_static::lazy_static;
+use lettre::EmailAddress;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long the candidates of a subscription by name can be chosen from
+const CANDIDATE_TIMEOUT: Duration = Duration::from_secs(60 * 10);
+const MAX_CANDIDATES: usize = 10;
+/// How many entries "记录" shows
+const AUDIT_LIMIT: u32 = 10;
+
+/// Candidate courses waiting to be chosen, keyed by the user and the chat
+/// they were found in. The tenant to subscribe is kept with them.
+struct Candidates {
+    found_at: Instant,
+    tenant: Tenant,
+    courses: Vec<CourseSummary>,
+}
+
+/// How long a confirmation code mailed to a new address is valid
+const EMAIL_CONFIRM_TIMEOUT: Duration = Duration::from_secs(60 * 30);
+
+/// An address waiting for its confirmation code before it is linked
+struct EmailConfirmation {
+    sent_at: Instant,
+    address: String,
+    digest: bool,
+    code: String,
+}
+
+lazy_static! {
+    static ref CANDIDATES: Mutex<HashMap<(u32, Tenant), Candidates>> = Mutex::new(HashMap::new());
+    static ref EMAIL_CONFIRMATIONS: Mutex<HashMap<u32, EmailConfirmation>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Split the optional target at the end of the arguments of "订阅" from the
+/// course ID or name before it.
+fn split_target<'a, 'b>(params: &'b [&'a str]) -> (&'b [&'a str], &'b [&'a str]) {
+    let at = match params {
+        [.., "webhook", _] => params.len() - 2,
+        [.., "邮件"] | [.., "email"] => params.len() - 1,
+        _ => params.len(),
+    };
+    params.split_at(at)
+}
+
+/// Split the first word off the arguments, keeping the line breaks of the
+/// rest.
+fn split_first_word(args: &str) -> (&str, &str) {
+    let mut parts = args.trim_start().splitn(2, char::is_whitespace);
+    let word = parts.next().unwrap_or_default();
+    (word, parts.next().unwrap_or_default().trim())
+}
+
+/// Record a change to the audit log. Failures are only logged, the change
+/// itself is already made.
+async fn audit(user_id: u32, tenant: Tenant, action: AuditAction) {
+    if let Err(e) = record_audit(user_id, tenant, action).await {
+        log(
+            LogLevel::Warning,
+            "permission",
+            format!("无法记录 {:?} {:#?}", action, e),
+        );
+    }
+}
+
+/// Check whether a sender with `role` in the chat the command is sent in may
+/// change the subscriptions of `tenant`, the chat itself or a target parsed by
+/// `parse_target`.
+async fn check_manage(role: Role, tenant: Tenant, language: Language) -> Result<(), String> {
+    match tenant {
+        // Personal subscriptions
+        Tenant::SenderSelf | Tenant::Email(_) => return Ok(()),
+        Tenant::Webhook(_) if role < Role::Superuser => {
+            return Err(tr(language, "target.webhook_superuser").to_string())
+        }
+        _ if role >= Role::Admin => return Ok(()),
+        _ => {}
+    }
+    match get_group_policy(tenant).await {
+        Ok(GroupPolicy::Anyone) => Ok(()),
+        Ok(GroupPolicy::Admin) => Err(tr(language, "hint.admin_only").to_string()),
+        Err(e) => Err(e.localize(language)),
+    }
+}
+
+fn policy_name(policy: GroupPolicy, language: Language) -> &'static str {
+    match policy {
+        GroupPolicy::Anyone => tr(language, "policy.anyone"),
+        GroupPolicy::Admin => tr(language, "policy.admin"),
+    }
+}
+
+fn format_audit(entries: &[AuditEntry], language: Language) -> String {
+    if entries.is_empty() {
+        return tr(language, "audit.empty").to_string();
+    }
+    let mut msg = trf(language, "audit.title", &[&entries.len()]);
+    for entry in entries {
+        let course_name = course_display_name(entry.course_name.as_deref(), language);
+        let action = match entry.action {
+            AuditAction::Subscribe(course_id) => {
+                trf(language, "audit.subscribe", &[&course_id, &course_name])
+            }
+            AuditAction::Unsubscribe(course_id) => {
+                trf(language, "audit.unsubscribe", &[&course_id, &course_name])
+            }
+            AuditAction::SetPolicy(policy) => {
+                trf(language, "audit.policy", &[&policy_name(policy, language)])
+            }
+            AuditAction::SetTemplate(kind) => {
+                trf(language, "audit.template", &[&kind.name(language)])
+            }
+            AuditAction::SetLanguage(Some(l)) => trf(language, "audit.language", &[&l.name()]),
+            AuditAction::SetLanguage(None) => tr(language, "audit.default_language").to_string(),
+        };
+        msg.push_str(
+            format!(
+                "\n{} {} {}",
+                format_time(
+                    Utc.from_utc_datetime(&entry.created_at).timestamp(),
+                    language
+                ),
+                entry.nickname,
+                action
+            )
+            .as_str(),
+        );
+    }
+    msg
+}
+
+/// Subscribe to the courses one by one and summarize the results.
+async fn subscribe_all(
+    user_id: u32,
+    courses: &[CourseSummary],
+    tenant: Tenant,
+    language: Language,
+) -> String {
+    if courses.is_empty() {
+        return tr(language, "subscribe_all.empty").to_string();
+    }
+    let mut added = 0;
+    let mut duplicated = 0;
+    let mut failures = Vec::new();
+    for course in courses {
+        match add_subscribe(user_id, course.id, tenant).await {
+            Ok(()) => {
+                audit(user_id, tenant, AuditAction::Subscribe(course.id)).await;
+                added += 1
+            }
+            Err(Error::Duplicated) => duplicated += 1,
+            Err(e) => failures.push(trf(
+                language,
+                "subscribe_all.failure",
+                &[&course.id, &course.full_name, &e.localize(language)],
+            )),
+        }
+    }
+    let mut msg = trf(language, "subscribe_all.added", &[&added]);
+    if duplicated > 0 {
+        msg.push_str(trf(language, "subscribe_all.duplicated", &[&duplicated]).as_str());
+    }
+    if !failures.is_empty() {
+        msg.push_str(
+            trf(
+                language,
+                "subscribe_all.failed",
+                &[&failures.len(), &failures.join("\n")],
+            )
+            .as_str(),
+        );
+    }
+    msg
+}
+
+fn format_templates(templates: &Templates) -> String {
+    let language = templates.language();
+    let mut msg = tr(language, "template.title").to_string();
+    for &kind in TEMPLATE_KINDS {
+        msg.push_str(
+            format!(
+                "\n{}{} {}",
+                if templates.is_custom(kind) { "*" } else { "" },
+                kind.name(language),
+                templates.get(kind).source()
+            )
+            .as_str(),
+        );
+    }
+    msg.push_str(tr(language, "template.variables"));
+    for name in VARIABLES {
+        let description = tr(language, format!("template.variable.{}", name).as_str());
+        msg.push_str(format!("\n{{{{{}}}}} {}", name, description).as_str());
+    }
+    msg.push_str(tr(language, "template.hint"));
+    msg
+}
+
+/// Show, customize or restore the templates of `tenant`. `args` are the
+/// arguments of "模板".
+async fn run_template(
+    user_id: u32,
+    role: Role,
+    command: &Command,
+    args: &str,
+    tenant: Tenant,
+    language: Language,
+) -> Result<String, Error> {
+    let templates = get_templates(tenant, user_id, language).await?;
+    let (word, source) = split_first_word(args);
+    if word.is_empty() {
+        return Ok(format_templates(&templates));
+    }
+    let kind = match TemplateKind::find(word) {
+        Some(kind) => kind,
+        None => {
+            let names: Vec<_> = TEMPLATE_KINDS.iter().map(|k| k.name(language)).collect();
+            return Ok(trf(
+                language,
+                "template.unknown_kind",
+                &[
+                    &word,
+                    &names.join(tr(language, "list.separator")),
+                    &command.usage(language),
+                ],
+            ));
+        }
+    };
+    if source.is_empty() {
+        return Ok(trf(
+            language,
+            "template.show",
+            &[&kind.name(language), &templates.get(kind).source()],
+        ));
+    }
+    if role < Role::Admin {
+        return Ok(tr(language, "template.admin_only").to_string());
+    }
+    let template = match source {
+        "默认" | "default" => None,
+        _ => match Template::parse(source) {
+            Ok(template) => Some(template),
+            Err(e) => return Ok(trf(language, "template.invalid", &[&e.localize(language)])),
+        },
+    };
+    set_template(tenant, user_id, kind, template.as_ref()).await?;
+    audit(user_id, tenant, AuditAction::SetTemplate(kind)).await;
+    Ok(match template {
+        Some(_) => trf(language, "template.saved", &[&kind.name(language)]),
+        None => trf(language, "template.restored", &[&kind.name(language)]),
+    })
+}
+
+/// Show or choose the language. In private it is the language of the user,
+/// in groups the language of the chat, which only admins may choose.
+async fn run_language(
+    user_id: u32,
+    role: Role,
+    is_private: bool,
+    command: &Command,
+    word: Option<&str>,
+    tenant: Tenant,
+    language: Language,
+) -> Result<String, Error> {
+    let word = match word {
+        Some(word) => word,
+        None => {
+            let name =
+                |l: Option<Language>| l.map_or(tr(language, "language.unset"), Language::name);
+            let mut msg = trf(language, "language.show", &[&language.name()]);
+            if !is_private {
+                let chat_language = get_chat_language(tenant).await?;
+                msg.push_str(trf(language, "language.chat", &[&name(chat_language)]).as_str());
+            }
+            let user_language = get_user_language(user_id).await?;
+            msg.push_str(trf(language, "language.user", &[&name(user_language)]).as_str());
+            msg.push_str(tr(language, "language.hint"));
+            return Ok(msg);
+        }
+    };
+    let new_language = match word {
+        "默认" | "default" => None,
+        _ => match Language::find(word) {
+            Some(l) => Some(l),
+            None => {
+                let names: Vec<_> = LANGUAGES.iter().map(|l| l.name()).collect();
+                return Ok(trf(
+                    language,
+                    "language.unknown",
+                    &[
+                        &word,
+                        &names.join(tr(language, "list.separator")),
+                        &command.usage(language),
+                    ],
+                ));
+            }
+        },
+    };
+    if is_private {
+        set_user_language(user_id, new_language).await?;
+        // Notifications to a private Telegram chat follow the chat
+        if let Tenant::Telegram(_) = tenant {
+            set_chat_language(tenant, new_language).await?;
+        }
+    } else {
+        if role < Role::Admin {
+            return Ok(tr(language, "language.admin_only").to_string());
+        }
+        set_chat_language(tenant, new_language).await?;
+        audit(user_id, tenant, AuditAction::SetLanguage(new_language)).await;
+    }
+    Ok(match new_language {
+        Some(l) => trf(l, "language.done", &[&l.name()]),
+        None => trf(
+            CONFIG.language,
+            "language.reset",
+            &[&CONFIG.language.name()],
+        ),
+    })
+}
+
+fn format_candidates(courses: &[CourseSummary], language: Language) -> String {
+    let mut msg = trf(language, "candidates.title", &[&courses.len()]);
+    for (i, course) in courses.iter().take(MAX_CANDIDATES).enumerate() {
+        msg.push_str(
+            format!(
+                "\n{}. {} {} ({})",
+                i + 1,
+                course.id,
+                course.full_name,
+                course.short_name
+            )
+            .as_str(),
+        );
+    }
+    if courses.len() > MAX_CANDIDATES {
+        msg.push_str(tr(language, "candidates.too_many"));
+    }
+    msg.push_str(tr(language, "candidates.hint"));
+    msg
+}
+
+async fn subscribe(
+    user_id: u32,
+    course_id: u32,
+    tenant: Tenant,
+    language: Language,
+) -> Result<String, Error> {
+    match add_subscribe(user_id, course_id, tenant).await {
+        Ok(()) => {
+            audit(user_id, tenant, AuditAction::Subscribe(course_id)).await;
+            Ok(tr(language, "subscribe.added").to_string())
+        }
+        Err(Error::Duplicated) => Ok(tr(language, "subscribe.duplicated").to_string()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Parse the optional "webhook [编号]" or "邮件" at the end of a command,
+/// which redirects it to the webhook or the mailbox of the user instead of
+/// the current chat. Only superusers may use webhooks.
+fn parse_target<'a>(
+    mut params: impl Iterator<Item = &'a str>,
+    user_id: u32,
+    role: Role,
+    tenant: Tenant,
+    language: Language,
+) -> Result<Tenant, String> {
+    match (params.next(), params.next()) {
+        (None, _) => Ok(tenant),
+        (Some("webhook"), _) if role < Role::Superuser => {
+            Err(tr(language, "target.webhook_superuser").to_string())
+        }
+        (Some("webhook"), Some(id)) => match id.parse() {
+            Ok(id) if CONFIG.webhooks.contains_key(&id) => Ok(Tenant::Webhook(id)),
+            _ => Err(trf(language, "target.no_webhook", &[&id])),
+        },
+        (Some("webhook"), None) => Err(tr(language, "target.missing_webhook").to_string()),
+        (Some("邮件"), None) | (Some("email"), None) => Ok(Tenant::Email(user_id)),
+        (Some(param), _) => Err(trf(language, "target.unknown", &[&param])),
+    }
+}
+
+async fn run_email(
+    user_id: u32,
+    address: Option<&str>,
+    digest: bool,
+    language: Language,
+) -> Result<String, Error> {
+    match address {
+        None => Ok(match get_user_email(user_id).await? {
+            Some((email, true)) => trf(language, "email.digest", &[&email]),
+            Some((email, false)) => trf(language, "email.instant", &[&email]),
+            None => tr(language, "email.hint").to_string(),
+        }),
+        Some("取消") | Some("cancel") => {
+            set_user_email(user_id, None, false).await?;
+            Ok(tr(language, "email.removed").to_string())
+        }
+        // lettre accepts anything ending with "localhost"
+        Some(address)
+            if address.contains('@') && EmailAddress::new(address.to_string()).is_ok() =>
+        {
+            if CONFIG.email.host.is_none() {
+                return Ok(tr(language, "email.no_server").to_string());
+            }
+            // Only switching between instant mails and the digest
+            if matches!(get_user_email(user_id).await?, Some((email, _)) if email == address) {
+                set_user_email(user_id, Some(address), digest).await?;
+                return Ok(trf(language, "email.linked", &[&address]));
+            }
+            let code = format!("{:06}", rand::thread_rng().gen_range(0, 1_000_000));
+            send_confirmation(address, code.as_str(), language).await?;
+            EMAIL_CONFIRMATIONS
+                .lock()
+                .expect("Email confirmations lock poisoned")
+                .insert(
+                    user_id,
+                    EmailConfirmation {
+                        sent_at: Instant::now(),
+                        address: address.to_string(),
+                        digest,
+                        code,
+                    },
+                );
+            Ok(trf(language, "email.confirm_sent", &[&address]))
+        }
+        Some(address) => Ok(trf(language, "email.invalid", &[&address])),
+    }
+}
+
+/// Link the address waiting for confirmation if `code` matches. A wrong code
+/// discards it, so codes cannot be guessed.
+async fn confirm_email(user_id: u32, code: &str, language: Language) -> Result<String, Error> {
+    let confirmation = EMAIL_CONFIRMATIONS
+        .lock()
+        .expect("Email confirmations lock poisoned")
+        .remove(&user_id)
+        .filter(|c| c.sent_at.elapsed() < EMAIL_CONFIRM_TIMEOUT && c.code == code);
+    match confirmation {
+        Some(c) => {
+            set_user_email(user_id, Some(c.address.as_str()), c.digest).await?;
+            Ok(trf(language, "email.linked", &[&c.address]))
+        }
+        None => Ok(tr(language, "email.confirm_invalid").to_string()),
+    }
+}
+
+fn format_subscriptions(subscriptions: &[SubscriptionInfo], language: Language) -> String {
+    if subscriptions.is_empty() {
+        return tr(language, "subscriptions.empty").to_string();
+    }
+    let mut msg = trf(language, "subscriptions.title", &[&subscriptions.len()]);
+    for s in subscriptions {
+        let status = match (s.token_expired, s.failure_count) {
+            (true, _) => tr(language, "subscriptions.expired").to_string(),
+            (false, 0) => tr(language, "subscriptions.ok").to_string(),
+            (false, n) if !CONFIG.schedule.is_paused(n) => {
+                trf(language, "subscriptions.failed", &[&n])
+            }
+            (false, n) => trf(language, "subscriptions.paused", &[&n]),
+        };
+        msg.push_str(
+            trf(
+                language,
+                "subscriptions.item",
+                &[
+                    &s.course_id,
+                    &course_display_name(s.course_name.as_deref(), language),
+                    &s.subscriber,
+                    &format_time(Utc.from_utc_datetime(&s.created_at).timestamp(), language),
+                    &status,
+                ],
+            )
+            .as_str(),
+        );
+    }
+    msg
+}
+
+/// Parse the course ID argument of a command.
+fn parse_course_id(
+    param: Option<&str>,
+    command: &Command,
+    language: Language,
+) -> Result<u32, String> {
+    match param {
+        Some(param) => param.parse().map_err(|_| {
+            trf(
+                language,
+                "course_id.invalid",
+                &[&param, &command.usage(language)],
+            )
+        }),
+        None => Err(trf(
+            language,
+            "course_id.missing",
+            &[&command.usage(language)],
+        )),
+    }
+}
+
+/// Run a command of a logged in user. `role` is the role of the sender in
+/// `tenant`, the chat the command is sent in. `args` is the message after the
+/// command name. Replies are in `language`.
+async fn run_command(
+    user_id: u32,
+    role: Role,
+    is_private: bool,
+    command: &Command,
+    args: &str,
+    tenant: Tenant,
+    language: Language,
+) -> String {
+    let mut params = args.split_ascii_whitespace();
+    let with_usage = |hint: String| format!("{}\n{}", hint, command.usage(language));
+    let msg = match command.kind {
+        CommandKind::Subscribe => {
+            let params: Vec<_> = params.collect();
+            let (query, target) = split_target(params.as_slice());
+            let target_tenant =
+                match parse_target(target.iter().copied(), user_id, role, tenant, language) {
+                    Ok(tenant) => tenant,
+                    Err(hint) => return with_usage(hint),
+                };
+            if target_tenant == Tenant::Email(user_id)
+                && !matches!(get_user_email(user_id).await, Ok(Some(_)))
+            {
+                return tr(language, "subscribe.email_required").to_string();
+            }
+            if let Err(hint) = check_manage(role, target_tenant, language).await {
+                return hint;
+            }
+            match query {
+                [] => {
+                    return trf(
+                        language,
+                        "subscribe.missing_query",
+                        &[&command.usage(language)],
+                    )
+                }
+                [id] if id.parse::<u32>().is_ok() => {
+                    subscribe(user_id, id.parse().unwrap(), target_tenant, language).await
+                }
+                _ => {
+                    let query = query.join(" ");
+                    match find_courses(user_id, query.as_str()).await {
+                        Ok(courses) => match courses.as_slice() {
+                            [] => Ok(trf(language, "subscribe.not_found", &[&query])),
+                            [course] => subscribe(user_id, course.id, target_tenant, language)
+                                .await
+                                .map(|msg| format!("{} {}", msg, course.full_name)),
+                            _ => {
+                                let msg = format_candidates(courses.as_slice(), language);
+                                CANDIDATES.lock().expect("Candidates lock poisoned").insert(
+                                    (user_id, tenant),
+                                    Candidates {
+                                        found_at: Instant::now(),
+                                        tenant: target_tenant,
+                                        courses,
+                                    },
+                                );
+                                Ok(msg)
+                            }
+                        },
+                        Err(err) => Err(err),
+                    }
+                }
+            }
+        }
+        CommandKind::SubscribeAll => {
+            let target_tenant = match parse_target(params, user_id, role, tenant, language) {
+                Ok(tenant) => tenant,
+                Err(hint) => return with_usage(hint),
+            };
+            if target_tenant == Tenant::Email(user_id)
+                && !matches!(get_user_email(user_id).await, Ok(Some(_)))
+            {
+                return tr(language, "subscribe.email_required").to_string();
+            }
+            if let Err(hint) = check_manage(role, target_tenant, language).await {
+                return hint;
+            }
+            match get_current_courses(user_id).await {
+                Ok(courses) => {
+                    Ok(subscribe_all(user_id, courses.as_slice(), target_tenant, language).await)
+                }
+                Err(err) => Err(err),
+            }
+        }
+        CommandKind::Select => {
+            let index = match params.next().map(|p| p.parse::<usize>()) {
+                Some(Ok(index)) => index,
+                Some(Err(_)) => {
+                    return trf(language, "select.invalid", &[&command.usage(language)])
+                }
+                None => return trf(language, "select.missing", &[&command.usage(language)]),
+            };
+            let candidates = CANDIDATES
+                .lock()
+                .expect("Candidates lock poisoned")
+                .remove(&(user_id, tenant))
+                .filter(|c| c.found_at.elapsed() < CANDIDATE_TIMEOUT);
+            let candidates = match candidates {
+                Some(candidates) => candidates,
+                None => return tr(language, "select.no_candidates").to_string(),
+            };
+            let course = match index
+                .checked_sub(1)
+                .and_then(|i| candidates.courses.get(i))
+                .filter(|_| index <= MAX_CANDIDATES)
+            {
+                Some(course) => course,
+                None => {
+                    let msg = trf(language, "select.not_found", &[&index]);
+                    // Let the user choose again
+                    CANDIDATES
+                        .lock()
+                        .expect("Candidates lock poisoned")
+                        .insert((user_id, tenant), candidates);
+                    return msg;
+                }
+            };
+            if let Err(hint) = check_manage(role, candidates.tenant, language).await {
+                return hint;
+            }
+            subscribe(user_id, course.id, candidates.tenant, language)
+                .await
+                .map(|msg| format!("{} {}", msg, course.full_name))
+        }
+        CommandKind::Unsubscribe => {
+            let course_id = match parse_course_id(params.next(), command, language) {
+                Ok(course_id) => course_id,
+                Err(hint) => return hint,
+            };
+            let target_tenant = match parse_target(params, user_id, role, tenant, language) {
+                Ok(tenant) => tenant,
+                Err(hint) => return with_usage(hint),
+            };
+            if let Err(hint) = check_manage(role, target_tenant, language).await {
+                return hint;
+            }
+            // Admins may remove the subscriptions of others in their chats
+            let removed = match target_tenant {
+                Tenant::Group(_) | Tenant::Telegram(_) if role >= Role::Admin => {
+                    remove_chat_subscribe(course_id, target_tenant).await
+                }
+                _ => remove_subscribe(user_id, course_id, target_tenant).await,
+            };
+            match removed {
+                Ok(()) => {
+                    audit(user_id, target_tenant, AuditAction::Unsubscribe(course_id)).await;
+                    Ok(tr(language, "unsubscribe.done").to_string())
+                }
+                Err(Error::NotExist) => Ok(tr(language, "unsubscribe.not_found").to_string()),
+                Err(err) => Err(err),
+            }
+        }
+        CommandKind::List => {
+            let tenant = match parse_target(params, user_id, role, tenant, language) {
+                Ok(tenant) => tenant,
+                Err(hint) => return with_usage(hint),
+            };
+            list_tenant_subscriptions(tenant, user_id)
+                .await
+                .map(|s| format_subscriptions(s.as_slice(), language))
+        }
+        CommandKind::Resume => {
+            let params: Vec<_> = params.collect();
+            let (course_id, target) = match params.split_first() {
+                Some((id, target)) if id.parse::<u32>().is_ok() => (id.parse().ok(), target),
+                _ => (None, params.as_slice()),
+            };
+            let tenant = match parse_target(target.iter().copied(), user_id, role, tenant, language)
+            {
+                Ok(tenant) => tenant,
+                Err(hint) => return with_usage(hint),
+            };
+            if let Err(hint) = check_manage(role, tenant, language).await {
+                return hint;
+            }
+            match reset_failures(tenant, user_id, course_id).await {
+                Ok(0) => Ok(tr(language, "resume.none").to_string()),
+                Ok(count) => Ok(trf(language, "resume.done", &[&count])),
+                Err(err) => Err(err),
+            }
+        }
+        CommandKind::Email => match (params.next(), params.next()) {
+            (Some("确认"), Some(code)) | (Some("confirm"), Some(code)) => {
+                confirm_email(user_id, code, language).await
+            }
+            (address, None) => run_email(user_id, address, false, language).await,
+            (address, Some("每日")) | (address, Some("daily")) => {
+                run_email(user_id, address, true, language).await
+            }
+            _ => return command.usage(language),
+        },
+        CommandKind::Policy => {
+            if is_private {
+                return tr(language, "policy.private").to_string();
+            }
+            let policy = match params.next() {
+                None => {
+                    return match get_group_policy(tenant).await {
+                        Ok(policy) => {
+                            trf(language, "policy.show", &[&policy_name(policy, language)])
+                        }
+                        Err(e) => e.localize(language),
+                    }
+                }
+                Some("所有人") | Some("anyone") => GroupPolicy::Anyone,
+                Some("管理员") | Some("admin") => GroupPolicy::Admin,
+                Some(_) => return command.usage(language),
+            };
+            if role < Role::Admin {
+                return tr(language, "policy.admin_only").to_string();
+            }
+            match set_group_policy(tenant, policy).await {
+                Ok(()) => {
+                    audit(user_id, tenant, AuditAction::SetPolicy(policy)).await;
+                    Ok(trf(
+                        language,
+                        "policy.done",
+                        &[&policy_name(policy, language)],
+                    ))
+                }
+                Err(err) => Err(err),
+            }
+        }
+        CommandKind::Template => run_template(user_id, role, command, args, tenant, language).await,
+        CommandKind::Language => {
+            run_language(
+                user_id,
+                role,
+                is_private,
+                command,
+                params.next(),
+                tenant,
+                language,
+            )
+            .await
+        }
+        CommandKind::Log => {
+            let tenant = match parse_target(params, user_id, role, tenant, language) {
+                Ok(tenant) => tenant,
+                Err(hint) => return with_usage(hint),
+            };
+            list_audit(tenant, user_id, AUDIT_LIMIT)
+                .await
+                .map(|entries| format_audit(entries.as_slice(), language))
+        }
+        CommandKind::Week => match get_tenant_courses(tenant, user_id).await {
+            Ok(course_ids) => list_upcoming_events(course_ids.as_slice(), language).await,
+            Err(err) => Err(err),
+        },
+        // Handled before looking up the user
+        _ => return help(Some(command.name), false, language),
+    };
+    msg.unwrap_or_else(|e| e.localize(language))
+}
+
+/// Split login arguments into the campus ID, the password and whether to
+/// remember it. The password is the rest of the line after the campus ID, so
+/// it may contain spaces. A trailing "记住" word asks to remember the password.
+fn split_login_args(args: &str) -> Option<(&str, &str, bool)> {
+    let (campus_id, rest) = split_first_word(args);
+    let (password, remember) = match rest.rsplitn(2, char::is_whitespace).collect::<Vec<_>>()[..] {
+        ["记住", password] | ["remember", password] => (password.trim_end(), true),
+        _ => (rest, false),
+    };
+    if campus_id.is_empty() || password.is_empty() {
+        None
+    } else {
+        Some((campus_id, password, remember))
+    }
+}
+
+async fn run_login(
+    account: Account,
+    nickname: &str,
+    command: &Command,
+    args: &str,
+    language: Language,
+) -> String {
+    let (campus_id, password, remember) = match split_login_args(args) {
+        Some((campus_id, password, remember)) => {
+            (campus_id.to_string(), password.to_string(), remember)
+        }
+        None => {
+            return format!(
+                "{}\n{}",
+                command.description(language),
+                command.usage(language)
+            )
+        }
+    };
+    match login_user(account, nickname, campus_id, password, remember).await {
+        Ok((_, 0)) => tr(language, "login.done").to_string(),
+        Ok((_, resumed)) => trf(language, "login.resumed", &[&resumed]),
+        Err(e) => trf(language, "login.failed", &[&e.localize(language)]),
+    }
+}
+
+/// Handle a message from `account` sent in `tenant`. `receiver` is the QQ to
+/// reply to for `Tenant::SenderSelf`. `role` is the role of the sender in a
+/// group, superusers are treated as owners of every chat. Replies are in the
+/// language of the chat, or of the sender.
+async fn handle_message(
+    account: Account,
+    nickname: &str,
+    tenant: Tenant,
+    receiver: i64,
+    is_private: bool,
+    role: Role,
+    msg: &str,
+) {
+    let mut params = msg.split_ascii_whitespace();
+    let (_, args) = split_first_word(msg);
+    let superuser = is_superuser(account);
+    let user_id = get_user_id(account).await;
+    let language = get_reply_language(tenant, is_private, user_id.as_ref().ok().copied()).await;
+    let command = params.next().map(|word| {
+        let command = find_command(word).filter(|c| superuser || !c.is_admin());
+        (word, command)
+    });
+    let command = match command {
+        Some((_, Some(command))) => command,
+        Some((word, None)) => {
+            let reply = trf(
+                language,
+                "hint.unknown_command",
+                &[&word, &tr(language, "hint.help")],
+            );
+            send_to_tenant(tenant, receiver, reply.as_str());
+            return;
+        }
+        None => {
+            send_to_tenant(tenant, receiver, tr(language, "hint.help"));
+            return;
+        }
+    };
+    match is_banned(account).await {
+        Ok(false) => {}
+        Ok(true) => {
+            send_to_tenant(tenant, receiver, tr(language, "hint.banned"));
+            return;
+        }
+        Err(e) => {
+            log(LogLevel::Error, "error", format!("无法读取用户状态 {}", e));
+            return;
+        }
+    }
+    if command.is_admin() {
+        let reply = if is_private {
+            run_admin_command(command, args, language).await
+        } else {
+            tr(language, "hint.admin_private").to_string()
+        };
+        send_to_tenant(tenant, receiver, reply.as_str());
+        return;
+    }
+    match command.kind {
+        CommandKind::Help => {
+            let reply = help(params.next(), superuser, language);
+            send_to_tenant(tenant, receiver, reply.as_str());
+            return;
+        }
+        CommandKind::Login => {
+            let reply = if is_private {
+                run_login(account, nickname, command, args, language).await
+            } else {
+                tr(language, "login.private").to_string()
+            };
+            send_to_tenant(tenant, receiver, reply.as_str());
+            return;
+        }
+        _ => {}
+    }
+    let user_id = match user_id {
+        Ok(i) => i,
+        Err(Error::NotExist) => {
+            send_to_tenant(tenant, receiver, tr(language, "hint.login"));
+            return;
+        }
+        Err(e) => {
+            log(LogLevel::Error, "error", format!("无法读取用户 ID {}", e));
+            return;
+        }
+    };
+    let role = if superuser {
+        Role::Superuser
+    } else if is_private {
+        Role::Owner
+    } else {
+        role
+    };
+    let reply = run_command(user_id, role, is_private, command, args, tenant, language).await;
+    send_to_tenant(tenant, receiver, reply.as_str());
+}
+
+/// Handle a private message from `user_qq`.
+pub async fn handle_private_message(user_qq: i64, nickname: &str, msg: &str) {
+    handle_message(
+        Account::Qq(user_qq),
+        nickname,
+        Tenant::SenderSelf,
+        user_qq,
+        true,
+        Role::Owner,
+        msg,
+    )
+    .await
+}
+
+/// Handle a group message mentioning the bot from a member with `role`.
+pub async fn handle_group_message(group_id: i64, user_qq: i64, role: Role, msg: &str) {
+    handle_message(
+        Account::Qq(user_qq),
+        "",
+        Tenant::Group(group_id),
+        0,
+        false,
+        role,
+        msg,
+    )
+    .await
+}
+
+/// Handle a Telegram message sent to the bot in private or mentioning it in
+/// a group. `role` is the role of the sender in the group.
+pub async fn handle_telegram_message(
+    chat_id: i64,
+    is_private: bool,
+    user_id: i64,
+    nickname: &str,
+    role: Role,
+    msg: &str,
+) {
+    handle_message(
+        Account::Telegram(user_id),
+        nickname,
+        Tenant::Telegram(chat_id),
+        0,
+        is_private,
+        role,
+        msg,
+    )
+    .await
+}
+
+/// Handle the bot leaving or being removed from a group.
+pub async fn handle_leave_group(group_id: i64, group_name: &str) {
+    let removed_subscribe_count = remove_group_subscribe(group_id)
+        .await
+        .unwrap_or_else(|_| panic!("Cannot remove group {}", group_id));
+    log(
+        LogLevel::Info,
+        "subscribe",
+        format!(
+            "已退订 {}({}) 群内的 {} 个订阅",
+            group_name, group_id, removed_subscribe_count
+        ),
+    );
+}
+
+/// Handle the bot leaving or being removed from a Telegram chat.
+pub async fn handle_leave_telegram_chat(chat_id: i64, title: &str) {
+    match remove_telegram_subscribe(chat_id).await {
+        Ok(count) => log(
+            LogLevel::Info,
+            "subscribe",
+            format!(
+                "已退订 Telegram {}({}) 内的 {} 个订阅",
+                title, chat_id, count
+            ),
+        ),
+        Err(e) => log(
+            LogLevel::Error,
+            "subscribe",
+            format!("无法退订 Telegram {} 内的订阅 {:#?}", chat_id, e),
+        ),
+    }
+}
+
+#[test]
+fn format_subscriptions_test() {
+    use chrono::NaiveDate;
+    let created_at = NaiveDate::from_ymd_opt(2020, 10, 1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .unwrap();
+    let zh = Language::Chinese;
+    assert_eq!(format_subscriptions(&[], zh), "还没有订阅课程呢");
+    let msg = format_subscriptions(
+        &[
+            SubscriptionInfo {
+                course_id: 1234,
+                course_name: Some("高等数学".to_string()),
+                subscriber: "小明".to_string(),
+                created_at,
+                failure_count: 0,
+                token_expired: false,
+            },
+            SubscriptionInfo {
+                course_id: 5678,
+                course_name: None,
+                subscriber: "小红".to_string(),
+                created_at,
+                failure_count: 3,
+                token_expired: false,
+            },
+        ],
+        zh,
+    );
+    assert_eq!(
+        msg,
+        format!(
+            "共 2 个订阅\n1234 高等数学\n  小明 订阅于 {0}，正常\n\
+            5678 未知课程\n  小红 订阅于 {0}，连续失败 3 次，已暂停",
+            format_time(Utc.from_utc_datetime(&created_at).timestamp(), zh)
+        )
+    );
+}
+
+#[test]
+fn split_target_test() {
+    let empty: &[&str] = &[];
+    assert_eq!(
+        split_target(&["高等", "数学", "webhook", "1"]),
+        (&["高等", "数学"][..], &["webhook", "1"][..])
+    );
+    assert_eq!(
+        split_target(&["1234", "邮件"]),
+        (&["1234"][..], &["邮件"][..])
+    );
+    assert_eq!(split_target(&["MAT101"]), (&["MAT101"][..], empty));
+}
+
+#[test]
+fn split_login_args_test() {
+    assert_eq!(split_login_args(""), None);
+    assert_eq!(split_login_args("12345"), None);
+    assert_eq!(
+        split_login_args("12345 secret"),
+        Some(("12345", "secret", false))
+    );
+    assert_eq!(
+        split_login_args("12345 my secret 记住"),
+        Some(("12345", "my secret", true))
+    );
+    assert_eq!(
+        split_login_args("12345 secret remember"),
+        Some(("12345", "secret", true))
+    );
+    assert_eq!(
+        split_login_args("12345 记住"),
+        Some(("12345", "记住", false))
+    );
+}
+
+#[test]
+fn format_audit_test() {
+    use chrono::NaiveDate;
+    let created_at = NaiveDate::from_ymd_opt(2020, 10, 1)
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+        .unwrap();
+    let zh = Language::Chinese;
+    assert_eq!(format_audit(&[], zh), "还没有变更记录呢");
+    let msg = format_audit(
+        &[
+            AuditEntry {
+                nickname: "小明".to_string(),
+                action: AuditAction::Unsubscribe(1234),
+                course_name: Some("高等数学".to_string()),
+                created_at,
+            },
+            AuditEntry {
+                nickname: "小红".to_string(),
+                action: AuditAction::SetPolicy(GroupPolicy::Anyone),
+                course_name: None,
+                created_at,
+            },
+        ],
+        zh,
+    );
+    assert_eq!(
+        msg,
+        format!(
+            "最近 2 条记录\n{0} 小明 退订了 1234 高等数学\n{0} 小红 设置为所有人可以修改订阅",
+            format_time(Utc.from_utc_datetime(&created_at).timestamp(), zh)
+        )
+    );
+}
@@ -1,167 +1,97 @@
+mod admin;
+mod assignment;
+mod calendar;
 mod check;
+mod command;
+mod config;
+mod course;
+mod credential;
 mod error;
+mod forum;
+mod handler;
+mod locale;
+mod messenger;
 mod migrations;
 mod moodle;
+mod permission;
+#[cfg(feature = "coolq")]
+mod plugin;
+mod schedule;
 mod subscribe;
+mod template;
 mod tenant;
 mod user;
 
+use crate::assignment::start_assignment_loop;
+use crate::calendar::start_calendar_loop;
 use crate::check::start_check_loop;
-use crate::subscribe::{add_subscribe, remove_group_subscribe, remove_subscribe};
-use crate::tenant::Tenant;
-use crate::user::get_user_id_from_qq;
-use coolq_sdk_rust::api::{
-    add_log, get_login_qq, send_group_msg, set_friend_add_request, set_group_add_request_v2,
-    CQLogLevel,
+use crate::config::{Backend, CONFIG};
+use crate::forum::start_forum_loop;
+use crate::messenger::{
+    log, serve_onebot, set_messenger, start_digest_loop, start_telegram_loop, LogLevel,
+    OneBotMessenger,
 };
-use coolq_sdk_rust::events::{
-    AddFriendRequestEvent, AddGroupRequestEvent, GroupMemberDecreaseEvent, GroupMessageEvent,
-    PrivateMessageEvent,
-};
-use coolq_sdk_rust::prelude::listener;
-use coolq_sdk_rust::targets::cqcode::CQCode;
-use coolq_sdk_rust::targets::group::Group;
 use lazy_static::lazy_static;
 use rusqlite::Connection;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tokio::sync::Mutex;
 
-static DATA_PATH: &'static str = "data/app/com.bdbai.moodle-sentinel";
-static DB_PATH: &'static str = "data/app/com.bdbai.moodle-sentinel/data.db";
-
 lazy_static! {
+    /// Directory of the database and `config.json`, which can be changed by
+    /// the `MOODLE_SENTINEL_DATA` environment variable
+    pub static ref DATA_PATH: PathBuf = std::env::var_os("MOODLE_SENTINEL_DATA")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("data/app/com.bdbai.moodle-sentinel"));
+    static ref DB_PATH: PathBuf = DATA_PATH.join("data.db");
     pub static ref CONN: Mutex<Connection> = {
-        std::fs::create_dir_all(DATA_PATH).expect("Cannot create data dir");
-        let mut conn = Connection::open(DB_PATH).expect("Cannot open or create db file");
+        std::fs::create_dir_all(&*DATA_PATH).expect("Cannot create data dir");
+        let mut conn = Connection::open(&*DB_PATH).expect("Cannot open or create db file");
         println!("Migrating...");
         migrations::runner()
             .run(&mut conn)
             .expect("Cannot run migration");
         Mutex::from(conn)
     };
-    pub static ref MY_QQ: i64 = get_login_qq().expect("Cannot parse my QQ").into();
 }
 
-#[coolq_sdk_rust::main]
-fn main() {
-    add_log(CQLogLevel::INFOSUCCESS, "info", "Moodle Sentinel 正在加载").expect("日志发送失败");
+fn migrate() {
+    log(LogLevel::Success, "info", "Moodle Sentinel 正在加载");
     let conn = CONN.try_lock().expect("Cannot acquire db conn lock");
     drop(conn);
-    add_log(CQLogLevel::INFOSUCCESS, "info", "数据库迁移完成").expect("日志发送失败");
-    coolq_sdk_rust::ASYNC_RUNTIME.spawn(start_check_loop());
-}
-
-#[listener]
-fn on_private_message(_event: PrivateMessageEvent) {
-    ()
+    log(LogLevel::Success, "info", "数据库迁移完成");
 }
 
-#[listener(priority = "low")]
-async fn on_group_message(event: GroupMessageEvent) {
-    let atme = event.msg.cqcodes.iter().any(|c| match c {
-        CQCode::At(qq) => MY_QQ.eq(qq),
-        _ => false,
-    });
-    if !atme {
-        return;
+/// Run the bot as a standalone process with the backend chosen in
+/// `config.json`, until the backend stops.
+pub async fn run() -> Result<(), error::Error> {
+    match CONFIG.backend {
+        Backend::OneBot => run_onebot().await,
     }
-    let group_id = event.group.group_id;
-    let user_id = match get_user_id_from_qq(event.user.user_id).await {
-        Ok(i) => i,
-        Err(error::Error::NotExist) => {
-            send_group_msg(group_id, "别急 再等等")
-                .or_else(|e| {
-                    add_log(
-                        CQLogLevel::ERROR,
-                        "group",
-                        format!("无法发送消息默认消息到群 {} 因为 {:#?}", group_id, e),
-                    )
-                })
-                .expect("Cannot add log");
-            return;
-        }
-        Err(e) => {
-            add_log(CQLogLevel::ERROR, "error", format!("无法读取用户 ID {}", e))
-                .expect("无法写入日志");
-            return;
-        }
-    };
-    let mut params = event.msg.msg.split_ascii_whitespace();
-    let command = match params.next() {
-        Some(s) => s,
-        None => return,
-    };
-    let param = match params.next().and_then(|p| p.parse().ok()) {
-        Some(s) => s,
-        None => return,
-    };
-    let tenant = Tenant::Group(group_id);
-    let msg = match command {
-        "订阅" => match add_subscribe(user_id, param, tenant).await {
-            Ok(()) => Ok("已添加订阅"),
-            Err(error::Error::Duplicated) => Ok("请不要重复订阅哦"),
-            Err(err) => Err(err),
-        },
-        "退订" => match remove_subscribe(user_id, param, tenant).await {
-            Ok(()) => Ok("已取消订阅"),
-            Err(error::Error::NotExist) => Ok("没有订阅过呢"),
-            Err(err) => Err(err),
-        },
-        _ => Ok("说啥呢 听不懂"),
-    };
-    let msg = msg
-        .map(|s| s.to_string())
-        .unwrap_or_else(|e| format!("{}", e));
-    send_group_msg(group_id, msg.as_str())
-        .or_else(|e| {
-            add_log(
-                CQLogLevel::ERROR,
-                "group",
-                format!("无法回复群消息\"{}\"到 {} 因为 {:#?}", msg, group_id, e),
-            )
-        })
-        .expect("Cannot add log");
 }
 
-#[listener]
-async fn group_member_decrease(event: GroupMemberDecreaseEvent) {
-    if MY_QQ.eq(&event.being_operate_user.user_id) {
-        // 被踢出群
-        let Group {
-            group_id,
-            group_name,
-            ..
-        } = event.group;
-        let removed_subscribe_count = remove_group_subscribe(group_id)
-            .await
-            .expect(format!("Cannot remove group {}", group_id).as_str());
-        add_log(
-            CQLogLevel::INFO,
-            "subscribe",
-            format!(
-                "已退订 {}({}) 群内的 {} 个订阅",
-                group_name, group_id, removed_subscribe_count
-            ),
-        )
-        .expect("无法写入日志");
+/// Run the bot with a OneBot v11 server (e.g. go-cqhttp) as the backend.
+async fn run_onebot() -> Result<(), error::Error> {
+    let messenger = Arc::new(OneBotMessenger::default());
+    set_messenger(messenger.clone());
+    migrate();
+    tokio::spawn(start_check_loop());
+    tokio::spawn(start_assignment_loop());
+    tokio::spawn(start_forum_loop());
+    tokio::spawn(start_calendar_loop());
+    if CONFIG.telegram.token.is_some() {
+        tokio::spawn(start_telegram_loop());
     }
-}
-
-#[listener]
-fn add_friend_request(event: AddFriendRequestEvent) {
-    set_friend_add_request(event.flag, true, "").expect("添加好友请求处理失败");
-}
-
-#[listener]
-fn add_group_request(event: AddGroupRequestEvent) {
-    if event.sub_type == 2 {
-        set_group_add_request_v2(event.flag, event.sub_type, true, "").expect("无法受邀加入群");
+    if CONFIG.email.host.is_some() {
+        tokio::spawn(start_digest_loop());
     }
+    serve_onebot(messenger).await
 }
 
 #[tokio::test]
 async fn init_migrate() -> Result<(), error::Error> {
-    if std::fs::metadata(DB_PATH).is_ok() {
-        std::fs::remove_file(DB_PATH).expect("Cannot delete old db");
+    if std::fs::metadata(&*DB_PATH).is_ok() {
+        std::fs::remove_file(&*DB_PATH).expect("Cannot delete old db");
     }
     let conn = CONN.try_lock().expect("Cannot acquire db conn lock");
     let login_result = moodle::login(env!("CQMS_CAMPUS_ID"), env!("CQMS_CAMPUS_PASSWORD")).await?;
@@ -0,0 +1,655 @@
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::messenger::{log, LogLevel};
+use crate::tenant::Tenant;
+use crate::CONN;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// Language of the messages sent by the bot
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "zh-CN")]
+    Chinese,
+    #[serde(rename = "en")]
+    English,
+}
+
+pub static LANGUAGES: &[Language] = &[Language::Chinese, Language::English];
+
+impl Language {
+    /// Code stored in the database and used in `config.json`
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::Chinese => "zh-CN",
+            Language::English => "en",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::Chinese => "中文",
+            Language::English => "English",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<Language> {
+        LANGUAGES.iter().copied().find(|l| l.code() == code)
+    }
+
+    /// Look up a language by its code or name.
+    pub fn find(word: &str) -> Option<Language> {
+        match word.to_ascii_lowercase().as_str() {
+            "zh" | "zh-cn" | "中文" | "chinese" => Some(Language::Chinese),
+            "en" | "英文" | "english" => Some(Language::English),
+            _ => None,
+        }
+    }
+}
+
+/// Messages in every language as (key, zh-CN, en). Each `{}` is replaced by
+/// the next argument.
+static MESSAGES: &[(&str, &str, &str)] = &[
+    // Hints
+    (
+        "hint.login",
+        "还没有绑定 Moodle 账号哦，请私聊我发送\"登录 [学号] [密码]\"",
+        "You have not linked a Moodle account yet, please send me \"login [student ID] [password]\" in private",
+    ),
+    ("hint.help", "发送\"帮助\"查看可用的命令", "Send \"help\" to see the available commands"),
+    (
+        "hint.banned",
+        "你已被禁止使用本 bot，如有疑问请联系管理员",
+        "You are banned from this bot, please contact the admins if you have any questions",
+    ),
+    (
+        "hint.admin_only",
+        "只有群主和管理员可以修改本群的订阅，可以请他们发送\"权限 所有人\"开放给所有人",
+        "Only the owner and admins can change the subscriptions of this group, they may send \"policy anyone\" to allow everyone",
+    ),
+    (
+        "hint.unknown_command",
+        "说啥呢 听不懂，没有\"{}\"这个命令\n{}",
+        "Sorry, there is no \"{}\" command\n{}",
+    ),
+    ("hint.admin_private", "管理命令只能在私聊中使用", "Admin commands are only available in private chats"),
+    ("list.separator", "、", ", "),
+    ("list.comma", "，", ", "),
+    ("time.format", "%m月%d日 %H:%M", "%b %d %H:%M"),
+    ("time.date", "%m月%d日", "%b %d"),
+    ("course.unknown", "未知课程", "Unknown course"),
+    // Help
+    ("help.usage", "用法：{}", "Usage: {}"),
+    ("help.aliases", "\n也可以用 {}", "\nAlso: {}"),
+    (
+        "help.unknown",
+        "没有\"{}\"这个命令，发送\"帮助\"查看所有命令",
+        "There is no \"{}\" command, send \"help\" to see all commands",
+    ),
+    ("help.title", "可以对我说：", "You can say:"),
+    ("help.admin", "\n管理命令（仅限私聊）：", "\nAdmin commands (private chats only):"),
+    ("help.hint", "\n发送\"帮助 [命令]\"查看命令的用法", "\nSend \"help [command]\" to see the usage of a command"),
+    // Commands, keyed by their first alias
+    ("command.help.args", "[命令]", "[command]"),
+    (
+        "command.help.description",
+        "查看所有命令，或某个命令的用法",
+        "Show all commands, or the usage of a command",
+    ),
+    ("command.login.args", "学号 密码 [记住]", "student-ID password [remember]"),
+    (
+        "command.login.description",
+        "绑定 Moodle 账号（仅限私聊），加上\"记住\"可以在登录过期时自动续期",
+        "Link your Moodle account (private chats only), add \"remember\" to renew the login automatically when it expires",
+    ),
+    ("command.subscribe.args", "课程ID或名称 [webhook 编号 | 邮件]", "course-ID-or-name [webhook ID | email]"),
+    (
+        "command.subscribe.description",
+        "订阅课程，有更新时发送到当前聊天、webhook 或邮箱",
+        "Subscribe to a course, updates are sent to this chat, a webhook or your mailbox",
+    ),
+    ("command.subscribeall.args", "[webhook 编号 | 邮件]", "[webhook ID | email]"),
+    (
+        "command.subscribeall.description",
+        "订阅本学期选修的所有课程",
+        "Subscribe to all courses enrolled in this semester",
+    ),
+    ("command.choose.args", "序号", "number"),
+    (
+        "command.choose.description",
+        "按名称订阅找到多个课程时，选择要订阅的课程",
+        "Choose the course to subscribe to when several courses are found by name",
+    ),
+    ("command.unsubscribe.args", "课程ID [webhook 编号 | 邮件]", "course-ID [webhook ID | email]"),
+    ("command.unsubscribe.description", "取消订阅课程", "Unsubscribe from a course"),
+    ("command.list.args", "[webhook 编号 | 邮件]", "[webhook ID | email]"),
+    (
+        "command.list.description",
+        "列出订阅的课程和检查状态",
+        "List the subscribed courses and their status",
+    ),
+    ("command.resume.args", "[课程ID] [webhook 编号 | 邮件]", "[course-ID] [webhook ID | email]"),
+    (
+        "command.resume.description",
+        "恢复因连续出错而暂停的订阅，不填课程 ID 则恢复全部",
+        "Resume the subscriptions paused after repeated failures, all of them without a course ID",
+    ),
+    ("command.email.args", "[地址 [每日] | 确认 验证码 | 取消]", "[address [daily] | confirm code | cancel]"),
+    (
+        "command.email.description",
+        "绑定接收通知的邮箱，加上\"每日\"改为每天发送一次摘要",
+        "Link a mailbox to receive notifications, add \"daily\" to receive a daily digest instead",
+    ),
+    ("command.policy.args", "[所有人 | 管理员]", "[anyone | admin]"),
+    (
+        "command.policy.description",
+        "查看或设置群内谁可以订阅和退订课程，默认只有群主和管理员",
+        "Show or set who can subscribe and unsubscribe in a group, only the owner and admins by default",
+    ),
+    ("command.template.args", "[类型 [内容 | 默认]]", "[kind [content | default]]"),
+    (
+        "command.template.description",
+        "查看或修改本聊天的更新通知模板，群内仅限群主和管理员修改",
+        "Show or change the notification templates of this chat, only the owner and admins can change them in groups",
+    ),
+    ("command.language.args", "[中文 | English | 默认]", "[中文 | English | default]"),
+    (
+        "command.language.description",
+        "查看或切换语言，私聊中设置自己的语言，群内由群主和管理员设置本群的语言",
+        "Show or switch the language, of yourself in private chats or of the group by the owner and admins",
+    ),
+    ("command.log.args", "[webhook 编号 | 邮件]", "[webhook ID | email]"),
+    (
+        "command.log.description",
+        "查看最近谁订阅或退订了课程",
+        "Show who subscribed to or unsubscribed from courses recently",
+    ),
+    ("command.week.args", "", ""),
+    (
+        "command.week.description",
+        "列出已订阅课程未来 7 天的日程",
+        "List the events of the subscribed courses in the coming 7 days",
+    ),
+    ("command.stats.args", "", ""),
+    (
+        "command.stats.description",
+        "查看用户、订阅和群的数量",
+        "Show the numbers of users, subscriptions and groups",
+    ),
+    ("command.users.args", "[页码]", "[page]"),
+    (
+        "command.users.description",
+        "列出所有用户及其订阅数量和状态",
+        "List all users with their subscriptions and status",
+    ),
+    ("command.groups.args", "", ""),
+    (
+        "command.groups.description",
+        "列出有订阅的 QQ 群和 Telegram 聊天",
+        "List the QQ groups and Telegram chats with subscriptions",
+    ),
+    ("command.reset-failures.args", "[telegram] 群号 课程ID", "[telegram] group-ID course-ID"),
+    (
+        "command.reset-failures.description",
+        "重置 QQ 群内课程订阅的失败次数，恢复已暂停的订阅，加上 telegram 则重置 Telegram 聊天的",
+        "Reset the failures of a course subscription in a QQ group to resume it, or in a Telegram chat with \"telegram\"",
+    ),
+    ("command.broadcast.args", "内容", "text"),
+    (
+        "command.broadcast.description",
+        "向所有有订阅的群和私聊发送通知",
+        "Send a notice to all groups and private chats with subscriptions",
+    ),
+    ("command.ban.args", "用户ID [解除]", "user-ID [undo]"),
+    (
+        "command.ban.description",
+        "禁止用户使用 bot 并暂停其订阅，加上\"解除\"取消封禁",
+        "Ban a user from the bot and pause the subscriptions, add \"undo\" to unban",
+    ),
+    // Login
+    ("login.private", "请私聊我登录，不要在群里发送密码哦", "Please log in in private, never send your password in a group"),
+    ("login.done", "登录成功，现在可以订阅课程了", "Logged in, now you can subscribe to courses"),
+    ("login.resumed", "登录成功，已恢复 {} 个订阅", "Logged in, {} subscriptions are resumed"),
+    ("login.failed", "登录失败，{}", "Failed to log in, {}"),
+    // Subscriptions
+    ("subscribe.added", "已添加订阅", "Subscribed"),
+    ("subscribe.duplicated", "请不要重复订阅哦", "Already subscribed"),
+    ("subscribe.missing_query", "缺少课程 ID 或名称\n{}", "Missing the course ID or name\n{}"),
+    ("subscribe.not_found", "没有找到和\"{}\"有关的课程", "No course matches \"{}\""),
+    ("subscribe.email_required", "请先发送\"邮箱 [地址]\"绑定邮箱", "Please link a mailbox with \"email [address]\" first"),
+    ("subscribe_all.empty", "本学期还没有选修的课程呢", "You are not enrolled in any course this semester"),
+    ("subscribe_all.added", "已订阅 {} 个课程", "Subscribed to {} courses"),
+    ("subscribe_all.duplicated", "，{} 个课程之前已经订阅过", ", {} of them were already subscribed"),
+    ("subscribe_all.failed", "\n{} 个课程订阅失败：\n{}", "\nFailed to subscribe to {} courses:\n{}"),
+    ("subscribe_all.failure", "{} {}：{}", "{} {}: {}"),
+    ("candidates.title", "找到 {} 个课程", "Found {} courses"),
+    ("candidates.too_many", "\n……结果太多，换个更准确的名称试试吧", "\n…too many results, try a more specific name"),
+    ("candidates.hint", "\n请发送\"选择 [序号]\"订阅", "\nSend \"choose [number]\" to subscribe"),
+    ("select.invalid", "序号应该是数字\n{}", "The number should be a number\n{}"),
+    ("select.missing", "缺少序号\n{}", "Missing the number\n{}"),
+    (
+        "select.no_candidates",
+        "请先发送\"订阅 [课程名称]\"查找课程",
+        "Please search for courses with \"subscribe [course name]\" first",
+    ),
+    ("select.not_found", "没有序号为 {} 的课程", "There is no course numbered {}"),
+    ("unsubscribe.done", "已取消订阅", "Unsubscribed"),
+    ("unsubscribe.not_found", "没有订阅过呢", "Not subscribed yet"),
+    ("resume.none", "没有出错的订阅", "No failed subscriptions"),
+    ("resume.done", "已恢复 {} 个订阅，下次检查时生效", "Resumed {} subscriptions, effective from the next check"),
+    ("course_id.invalid", "课程 ID \"{}\" 应该是数字\n{}", "The course ID \"{}\" should be a number\n{}"),
+    ("course_id.missing", "缺少课程 ID\n{}", "Missing the course ID\n{}"),
+    ("target.no_webhook", "没有编号为 {} 的 webhook", "There is no webhook {}"),
+    ("target.missing_webhook", "缺少 webhook 编号", "Missing the webhook ID"),
+    ("target.unknown", "看不懂\"{}\"", "Cannot understand \"{}\""),
+    (
+        "target.webhook_superuser",
+        "只有 bot 的超级用户可以操作 webhook 的订阅",
+        "Only superusers of the bot can manage the subscriptions of webhooks",
+    ),
+    ("subscriptions.empty", "还没有订阅课程呢", "No subscriptions yet"),
+    ("subscriptions.title", "共 {} 个订阅", "{} subscriptions"),
+    ("subscriptions.item", "\n{} {}\n  {} 订阅于 {}，{}", "\n{} {}\n  subscribed by {} on {}, {}"),
+    ("subscriptions.expired", "登录已过期，已暂停", "login expired, paused"),
+    ("subscriptions.ok", "正常", "OK"),
+    ("subscriptions.failed", "连续失败 {} 次", "failed {} times in a row"),
+    ("subscriptions.paused", "连续失败 {} 次，已暂停", "failed {} times in a row, paused"),
+    // Email
+    ("email.digest", "已绑定邮箱 {}，每天发送一次摘要", "Linked to {}, a digest is sent daily"),
+    ("email.instant", "已绑定邮箱 {}，有更新时立即发送", "Linked to {}, updates are sent immediately"),
+    (
+        "email.hint",
+        "请发送\"邮箱 [地址]\"绑定邮箱，在最后加上\"每日\"改为每天发送一次摘要",
+        "Send \"email [address]\" to link a mailbox, add \"daily\" at the end to receive a daily digest instead",
+    ),
+    ("email.removed", "已解绑邮箱，邮件订阅已全部取消", "The mailbox is unlinked and all email subscriptions are removed"),
+    ("email.no_server", "还没有配置邮件服务器呢", "No mail server is configured"),
+    (
+        "email.linked",
+        "已绑定邮箱 {}，现在可以发送\"订阅 [课程 ID] 邮件\"了",
+        "Linked to {}, now you can send \"subscribe [course ID] email\"",
+    ),
+    ("email.invalid", "{} 不是有效的邮箱地址", "{} is not a valid email address"),
+    (
+        "email.confirm_sent",
+        "验证码已发送到 {}，请在 30 分钟内发送\"邮箱 确认 [验证码]\"完成绑定",
+        "A confirmation code is sent to {}, please send \"email confirm [code]\" within 30 minutes to link it",
+    ),
+    (
+        "email.confirm_invalid",
+        "验证码错误或已过期，请重新绑定邮箱",
+        "The code is wrong or expired, please link the mailbox again",
+    ),
+    ("email.confirm_subject", "Moodle Sentinel 邮箱验证", "Confirm your mailbox for Moodle Sentinel"),
+    (
+        "email.confirm_body",
+        "你的验证码是 {}，30 分钟内有效。如果不是你本人操作，请忽略这封邮件。",
+        "Your confirmation code is {}, valid for 30 minutes. Please ignore this mail if you did not ask for it.",
+    ),
+    ("email.subject", "Moodle Sentinel 通知", "Moodle Sentinel notification"),
+    ("email.digest_subject", "Moodle 每日摘要 {}", "Moodle daily digest {}"),
+    ("email.update_subject", "{} 有更新", "{} has updates"),
+    ("email.publish", "发布了", "Published"),
+    ("email.update", "更新了", "Updated"),
+    ("email.show", "公开了", "Revealed"),
+    ("email.hide", "隐藏了", "Hid"),
+    ("email.remove", "移除了", "Removed"),
+    // Permissions
+    ("policy.anyone", "所有人", "everyone"),
+    ("policy.admin", "群主和管理员", "the owner and admins"),
+    ("policy.private", "私聊中的订阅只有你自己可以修改", "Only you can change the subscriptions of a private chat"),
+    ("policy.show", "本群{}可以修改订阅", "In this group, {} can change subscriptions"),
+    ("policy.admin_only", "只有群主和管理员可以设置权限", "Only the owner and admins can set the policy"),
+    ("policy.done", "已设置为{}可以修改订阅", "Now {} can change subscriptions"),
+    ("audit.empty", "还没有变更记录呢", "No changes yet"),
+    ("audit.title", "最近 {} 条记录", "Latest {} changes"),
+    ("audit.subscribe", "订阅了 {} {}", "subscribed to {} {}"),
+    ("audit.unsubscribe", "退订了 {} {}", "unsubscribed from {} {}"),
+    ("audit.policy", "设置为{}可以修改订阅", "allowed {} to change subscriptions"),
+    ("audit.template", "修改了{}通知模板", "changed the {} notification template"),
+    ("audit.language", "将语言设置为{}", "set the language to {}"),
+    ("audit.default_language", "恢复了默认语言", "restored the default language"),
+    // Templates
+    ("template.title", "通知模板（* 为自定义的模板）：", "Notification templates (* for customized ones):"),
+    ("template.variables", "\n可用变量：", "\nVariables:"),
+    (
+        "template.hint",
+        "\n发送\"模板 [类型] [内容]\"修改，\"模板 [类型] 默认\"恢复默认",
+        "\nSend \"template [kind] [content]\" to change a template, or \"template [kind] default\" to restore it",
+    ),
+    ("template.unknown_kind", "没有\"{}\"这种模板，可以是{}\n{}", "There is no \"{}\" template, it may be {}\n{}"),
+    ("template.show", "{}模板：\n{}", "The {} template:\n{}"),
+    (
+        "template.admin_only",
+        "只有群主和管理员可以修改通知模板",
+        "Only the owner and admins can change the notification templates",
+    ),
+    ("template.invalid", "模板有误，{}", "Invalid template, {}"),
+    ("template.saved", "已修改{}模板", "The {} template is changed"),
+    ("template.restored", "已恢复默认的{}模板", "The {} template is restored to default"),
+    ("template.error.unclosed_tag", "缺少 }}", "missing }}"),
+    ("template.error.unknown_variable", "没有 {} 这个变量", "there is no variable {}"),
+    ("template.error.else", "{{else}} 应该在 {{#if}} 中", "{{else}} should be inside {{#if}}"),
+    ("template.error.end_if", "多余的 {{/if}}", "unexpected {{/if}}"),
+    ("template.error.unclosed_if", "缺少 {{/if}}", "missing {{/if}}"),
+    ("template.variable.course_name", "课程名称", "name of the course"),
+    ("template.variable.module_name", "内容名称", "name of the item"),
+    ("template.variable.module_type", "内容类型，如文件、作业", "type of the item, such as file or assignment"),
+    (
+        "template.variable.visible",
+        "内容是否可见，用于 {{#if visible}}",
+        "whether the item is visible, for {{#if visible}}",
+    ),
+    ("template.variable.url", "内容的链接", "link to the item"),
+    ("template.variable.count", "批量变更的内容数量", "number of items changed together"),
+    // Languages
+    ("language.show", "当前语言：{}", "Current language: {}"),
+    ("language.chat", "\n本群设置：{}", "\nThis chat: {}"),
+    ("language.user", "\n个人设置：{}", "\nYour setting: {}"),
+    ("language.unset", "未设置", "not set"),
+    (
+        "language.hint",
+        "\n发送\"语言 [中文 | English]\"切换，\"语言 默认\"恢复默认",
+        "\nSend \"language [中文 | English]\" to switch, or \"language default\" to restore the default",
+    ),
+    (
+        "language.admin_only",
+        "只有群主和管理员可以设置本群的语言",
+        "Only the owner and admins can set the language of this group",
+    ),
+    ("language.done", "已切换到{}", "Switched to {}"),
+    ("language.reset", "已恢复默认语言{}", "Restored the default language {}"),
+    ("language.unknown", "不支持\"{}\"，可以是{}\n{}", "\"{}\" is not supported, it may be {}\n{}"),
+    // Course updates
+    ("module.video", "视频", "video"),
+    ("module.file", "文件", "file"),
+    ("module.link", "链接", "link"),
+    ("module.folder", "文件夹", "folder"),
+    ("module.page", "页面", "page"),
+    ("module.assignment", "作业", "assignment"),
+    ("module.other", "内容", "item"),
+    ("check.resumed", "{} 已恢复更新", "Updates of {} are resumed"),
+    (
+        "check.paused",
+        "更新 {} 连续出错 {} 次，已暂停，将于 {} 自动重试，也可以发送\"恢复 {}\"立即恢复\n{}",
+        "Checking {} failed {} times in a row and is paused until {}, or send \"resume {}\" to resume it now\n{}",
+    ),
+    (
+        "token.renew_failed",
+        "Moodle 登录过期了，自动重新登录失败：{}\n相关订阅已暂停，请发送\"登录 [学号] [密码]\"重新登录",
+        "The Moodle login has expired and logging in again failed: {}\n\
+        The subscriptions are paused, please send \"login [student ID] [password]\" to log in again",
+    ),
+    (
+        "token.expired",
+        "Moodle 登录过期了，相关订阅已暂停，请发送\"登录 [学号] [密码]\"重新登录。\
+        在最后加上\"记住\"可以自动续期",
+        "The Moodle login has expired and the subscriptions are paused, \
+        please send \"login [student ID] [password]\" to log in again. \
+        Add \"remember\" at the end to renew it automatically",
+    ),
+    ("offset.days", "{} 天", "{} day(s)"),
+    ("offset.hours", "{} 小时", "{} hour(s)"),
+    ("offset.minutes", "{} 分钟", "{} minute(s)"),
+    ("assignment.no_due_date", "取消了截止时间", "has no due date now"),
+    ("assignment.due_date", "截止时间改为 {}", "is now due on {}"),
+    ("assignment.no_cut_off_date", "取消了最晚提交时间", "has no cut-off date now"),
+    ("assignment.cut_off_date", "最晚提交时间改为 {}", "has the cut-off date moved to {}"),
+    ("assignment.changed", "{} 的作业 {} {}", "{}: the assignment {} {}"),
+    (
+        "assignment.reminder",
+        "{} 的作业 {} 将在 {} 截止，还有不到 {}，别忘了提交哦",
+        "{}: the assignment {} is due on {}, less than {} left, do not forget to submit it",
+    ),
+    ("calendar.new", "{} 新增日程 {}：{}", "{} added the event {}: {}"),
+    ("calendar.changed", "{} 的日程 {} 时间改为 {}", "{}: the event {} is moved to {}"),
+    ("calendar.renamed", "{} 的日程 {} 改名为 {}", "{}: the event {} is renamed to {}"),
+    (
+        "calendar.renamed_changed",
+        "{} 的日程 {} 改名为 {}，时间改为 {}",
+        "{}: the event {} is renamed to {} and moved to {}",
+    ),
+    ("calendar.removed", "{} 的日程 {}（{}）已取消", "{}: the event {} on {} is cancelled"),
+    ("calendar.course", "课程 {}", "Course {}"),
+    ("calendar.empty", "这周没有日程，好好休息吧", "No events this week, have a good rest"),
+    ("calendar.title", "这周的日程：\n{}", "Events this week:\n{}"),
+    ("forum.new", "{} 的{} 有新帖子：{}\n{}：{}", "{}: new discussion in {}: {}\n{}: {}"),
+    // Admin console
+    (
+        "admin.stats",
+        "用户 {} 个（登录过期 {}，已封禁 {}）\n\
+        订阅的课程 {} 个\n\
+        订阅 {} 个：群 {}，私聊 {}，Telegram {}，webhook {}，邮件 {}\n\
+        QQ 群 {} 个，Telegram 聊天 {} 个",
+        "{} users ({} login expired, {} banned)\n\
+        {} courses subscribed\n\
+        {} subscriptions: {} in groups, {} private, {} Telegram, {} webhook, {} email\n\
+        {} QQ groups, {} Telegram chats",
+    ),
+    ("admin.users.title", "共 {} 个用户，第 {}/{} 页", "{} users, page {}/{}"),
+    ("admin.users.subscriptions", "，{} 个订阅", ", {} subscriptions"),
+    ("admin.users.expired", "，登录已过期", ", login expired"),
+    ("admin.users.banned", "，已封禁", ", banned"),
+    ("admin.users.next", "\n发送\"用户 {}\"查看下一页", "\nSend \"users {}\" for the next page"),
+    ("admin.groups.qq", "QQ 群", "QQ group"),
+    ("admin.groups.empty", "还没有群订阅课程呢", "No group has subscribed to courses yet"),
+    ("admin.groups.title", "共 {} 个群", "{} groups"),
+    ("admin.groups.item", "\n{} {}：{} 个订阅", "\n{} {}: {} subscriptions"),
+    ("admin.groups.paused", "，{} 个已暂停", ", {} paused"),
+    ("admin.reset.none", "没有找到失败的订阅", "No failed subscriptions found"),
+    ("admin.reset.done", "已重置 {} 个订阅，下次检查时恢复", "Reset {} subscriptions, they resume from the next check"),
+    ("admin.broadcast.done", "已发送到 {} 个聊天", "Sent to {} chats"),
+    ("admin.ban.done", "已封禁用户 {} {}，其订阅已暂停", "Banned user {} {}, the subscriptions are paused"),
+    ("admin.ban.undone", "已解除封禁用户 {} {}", "Unbanned user {} {}"),
+    ("admin.ban.not_found", "没有 ID 为 {} 的用户", "There is no user with ID {}"),
+    // Errors
+    ("error.duplicated", "记录重复了，请删除再试", "The record already exists, please remove it and try again"),
+    ("error.not_exist", "记录不存在呢", "The record does not exist"),
+    ("error.db", "数据库出错了", "Database error"),
+    ("error.moodle_request", "和 Moodle 通讯时出错了", "Cannot reach Moodle"),
+    ("error.invalid_token", "Moodle 登录过期了", "The Moodle login has expired"),
+    ("error.invalid_login", "学号或密码错误", "Wrong student ID or password"),
+    ("error.moodle", "Moodle 出错了", "Moodle error"),
+    ("error.add_failed", "无法添加记录", "Cannot add the record"),
+    ("error.encrypt", "无法加密登录信息", "Cannot encrypt the login"),
+    ("error.decrypt", "无法解密登录信息", "Cannot decrypt the login"),
+    ("error.no_mail_server", "未配置邮件服务器", "No mail server is configured"),
+    ("error.send_mail", "无法发送邮件 {}", "Cannot send the mail {}"),
+    ("error.no_email", "用户 {} 没有绑定邮箱", "User {} has not linked an email address"),
+    ("error.no_webhook", "没有配置 webhook {}", "Webhook {} is not configured"),
+    ("error.no_telegram", "未配置 Telegram bot", "No Telegram bot is configured"),
+    ("error.telegram", "Telegram 出错了 {}", "Telegram error {}"),
+    ("error.telegram_token", "Telegram bot 的 token 无效", "The Telegram bot token is rejected"),
+    ("error.onebot", "OneBot 出错了 {}", "OneBot error {}"),
+    ("error.onebot_disconnected", "OneBot 未连接", "OneBot is not connected"),
+    ("error.coolq", "酷Q 出错了 {}", "CoolQ error {}"),
+    ("error.listen", "无法监听 {}，{}", "Cannot listen on {}, {}"),
+    ("error.listen_address", "无法解析监听地址 {}", "Cannot parse the listen address {}"),
+];
+
+lazy_static! {
+    static ref CATALOG: HashMap<&'static str, (&'static str, &'static str)> = MESSAGES
+        .iter()
+        .map(|&(key, zh, en)| (key, (zh, en)))
+        .collect();
+}
+
+/// Message `key` in `language`. Missing keys are logged and rendered empty.
+pub fn tr(language: Language, key: &str) -> &'static str {
+    match CATALOG.get(key) {
+        Some(&(zh, en)) => match language {
+            Language::Chinese => zh,
+            Language::English => en,
+        },
+        None => {
+            log(LogLevel::Warning, "locale", format!("缺少消息 {}", key));
+            ""
+        }
+    }
+}
+
+/// Message `key` in `language` with each `{}` replaced by the next argument.
+pub fn trf(language: Language, key: &str, args: &[&dyn Display]) -> String {
+    let mut args = args.iter();
+    let mut parts = tr(language, key).split("{}");
+    let mut msg = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            msg.push_str(arg.to_string().as_str());
+        }
+        msg.push_str(part);
+    }
+    msg
+}
+
+/// A message rendered in every language, delivered to each receiver in its
+/// own.
+#[derive(Debug, Clone)]
+pub struct Localized(HashMap<Language, String>);
+
+impl Localized {
+    pub fn new(render: impl Fn(Language) -> String) -> Localized {
+        Localized(LANGUAGES.iter().map(|&l| (l, render(l))).collect())
+    }
+
+    pub fn get(&self, language: Language) -> &str {
+        self.0[&language].as_str()
+    }
+}
+
+pub async fn get_user_language(user_id: u32) -> Result<Option<Language>, Error> {
+    let code: Option<String> = CONN.lock().await.query_row(
+        "SELECT `language` FROM `user` WHERE `id` = ?1",
+        params![user_id],
+        |row| row.get(0),
+    )?;
+    Ok(code.as_deref().and_then(Language::from_code))
+}
+
+/// Set the language of a user, or follow the default with `None`.
+pub async fn set_user_language(user_id: u32, language: Option<Language>) -> Result<(), Error> {
+    CONN.lock().await.execute(
+        "UPDATE `user` SET `language` = ?1 WHERE `id` = ?2",
+        params![language.map(Language::code), user_id],
+    )?;
+    Ok(())
+}
+
+/// Language chosen for a group or a Telegram chat
+pub async fn get_chat_language(tenant: Tenant) -> Result<Option<Language>, Error> {
+    let (tenant_type, tenant_id) = tenant.key(0);
+    let code: Option<String> = CONN
+        .lock()
+        .await
+        .query_row(
+            "SELECT `language` FROM `chat_language` WHERE `tenant_type` = ?1 AND `tenant_id` = ?2",
+            params![tenant_type, tenant_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(code.as_deref().and_then(Language::from_code))
+}
+
+/// Set the language of a group or a Telegram chat, or follow the default
+/// with `None`.
+pub async fn set_chat_language(tenant: Tenant, language: Option<Language>) -> Result<(), Error> {
+    let (tenant_type, tenant_id) = tenant.key(0);
+    let conn = CONN.lock().await;
+    match language {
+        Some(language) => conn.execute(
+            "INSERT OR REPLACE INTO `chat_language` (`tenant_type`, `tenant_id`, `language`, `updated_at`) \
+            VALUES (?1, ?2, ?3, ?4)",
+            params![tenant_type, tenant_id, language.code(), Utc::now().naive_utc()],
+        )?,
+        None => conn.execute(
+            "DELETE FROM `chat_language` WHERE `tenant_type` = ?1 AND `tenant_id` = ?2",
+            params![tenant_type, tenant_id],
+        )?,
+    };
+    Ok(())
+}
+
+fn or_default(language: Result<Option<Language>, Error>) -> Language {
+    match language {
+        Ok(language) => language.unwrap_or(CONFIG.language),
+        Err(e) => {
+            log(
+                LogLevel::Warning,
+                "locale",
+                format!("无法读取语言设置 {:#?}", e),
+            );
+            CONFIG.language
+        }
+    }
+}
+
+/// Language of the messages sent to `tenant`, with the same arguments as
+/// `send_to_tenant`. Personal tenants follow their user.
+pub async fn get_tenant_language(tenant: Tenant, user_qq: i64) -> Language {
+    let language = match tenant {
+        Tenant::SenderSelf => {
+            let code: Result<Option<Option<String>>, _> = CONN
+                .lock()
+                .await
+                .query_row(
+                    "SELECT `language` FROM `user` WHERE `qq` = ?1",
+                    params![user_qq],
+                    |row| row.get(0),
+                )
+                .optional();
+            code.map(|code| code.flatten().as_deref().and_then(Language::from_code))
+                .map_err(Error::from)
+        }
+        Tenant::Email(user_id) => get_user_language(user_id).await,
+        Tenant::Webhook(webhook_id) => {
+            Ok(CONFIG.webhooks.get(&webhook_id).and_then(|w| w.language))
+        }
+        Tenant::Group(_) | Tenant::Telegram(_) => get_chat_language(tenant).await,
+    };
+    or_default(language)
+}
+
+/// Language to reply to a user in `tenant`: the language of the group, then
+/// the language of the user if logged in.
+pub async fn get_reply_language(
+    tenant: Tenant,
+    is_private: bool,
+    user_id: Option<u32>,
+) -> Language {
+    let mut language = Ok(None);
+    if !is_private {
+        language = get_chat_language(tenant).await;
+    }
+    if let (Ok(None), Some(user_id)) = (&language, user_id) {
+        language = get_user_language(user_id).await;
+    }
+    or_default(language)
+}
+
+#[test]
+fn catalog_test() {
+    assert_eq!(CATALOG.len(), MESSAGES.len(), "Duplicated message keys");
+    for (key, zh, en) in MESSAGES {
+        assert_eq!(
+            zh.matches("{}").count(),
+            en.matches("{}").count(),
+            "Arguments of {} differ",
+            key
+        );
+    }
+    assert_eq!(
+        trf(Language::English, "login.resumed", &[&3]),
+        "Logged in, 3 subscriptions are resumed"
+    );
+    assert_eq!(
+        trf(Language::Chinese, "help.usage", &[&"帮助"]),
+        "用法：帮助"
+    );
+    assert_eq!(Language::find("EN"), Some(Language::English));
+    assert_eq!(Language::find("中文"), Some(Language::Chinese));
+}
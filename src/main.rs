@@ -0,0 +1,7 @@
+#[tokio::main]
+async fn main() {
+    if let Err(e) = moodle_sentinel::run().await {
+        eprintln!("Moodle Sentinel 已停止：{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,30 @@
+use crate::error::Error;
+use crate::messenger::{LogLevel, Messenger};
+use coolq_sdk_rust::api::{add_log, send_group_msg, send_private_msg, CQLogLevel};
+
+/// Messenger of the CoolQ plugin host
+pub struct CoolQMessenger;
+
+impl Messenger for CoolQMessenger {
+    fn send_group_msg(&self, group_id: i64, msg: &str) -> Result<(), Error> {
+        send_group_msg(group_id, msg)
+            .map(|_| ())
+            .map_err(|e| Error::Localized("error.coolq", vec![format!("{:?}", e)]))
+    }
+
+    fn send_private_msg(&self, user_id: i64, msg: &str) -> Result<(), Error> {
+        send_private_msg(user_id, msg)
+            .map(|_| ())
+            .map_err(|e| Error::Localized("error.coolq", vec![format!("{:?}", e)]))
+    }
+
+    fn log(&self, level: LogLevel, tag: &str, msg: &str) {
+        let level = match level {
+            LogLevel::Info => CQLogLevel::INFO,
+            LogLevel::Success => CQLogLevel::INFOSUCCESS,
+            LogLevel::Warning => CQLogLevel::WARNING,
+            LogLevel::Error => CQLogLevel::ERROR,
+        };
+        add_log(level, tag, msg).expect("Cannot add log");
+    }
+}
@@ -0,0 +1,332 @@
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::locale::{get_tenant_language, tr, trf, Language};
+use crate::messenger::{log, LogLevel};
+use crate::tenant::Tenant;
+use crate::user::get_user_email;
+use crate::CONN;
+use chrono::{Local, Timelike, Utc};
+use lettre::smtp::authentication::Credentials;
+use lettre::{ClientSecurity, ClientTlsParameters, SmtpClient, Transport};
+use lettre_email::EmailBuilder;
+use native_tls::TlsConnector;
+use rusqlite::params;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use tokio::time::{delay_for, Duration};
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct EmailConfig {
+    /// SMTP server. Email delivery is disabled without it.
+    pub host: Option<String>,
+    pub port: u16,
+    /// Upgrade a plain connection with STARTTLS instead of connecting with
+    /// TLS directly
+    pub starttls: bool,
+    pub username: String,
+    pub password: String,
+    /// Sender address, `username` if not set
+    pub from: Option<String>,
+    /// Local hour to send the daily digests at
+    pub digest_hour: u32,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        EmailConfig {
+            host: None,
+            port: 465,
+            starttls: false,
+            username: String::new(),
+            password: String::new(),
+            from: None,
+            digest_hour: 8,
+        }
+    }
+}
+
+/// One entry of a mail, either an update of a module or a plain message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EmailItem {
+    pub course_name: Option<String>,
+    pub text: String,
+    pub module_type: Option<String>,
+    pub module_name: Option<String>,
+    pub url: Option<String>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render the entries as HTML, grouped by course.
+fn render_html(items: &[EmailItem]) -> String {
+    let mut courses: BTreeMap<Option<&str>, Vec<&EmailItem>> = BTreeMap::new();
+    for item in items {
+        courses
+            .entry(item.course_name.as_deref())
+            .or_default()
+            .push(item);
+    }
+    let mut html = String::from("<html><body>");
+    for (course_name, items) in courses {
+        if let Some(course_name) = course_name {
+            html.push_str(format!("<h3>{}</h3>", escape(course_name)).as_str());
+        }
+        html.push_str("<ul>");
+        for item in items {
+            html.push_str("<li>");
+            html.push_str(escape(item.text.as_str()).as_str());
+            if let Some(module_type) = &item.module_type {
+                html.push_str(format!(" {} ", escape(module_type)).as_str());
+            }
+            let name = escape(item.module_name.as_deref().unwrap_or_default());
+            match &item.url {
+                Some(url) => html.push_str(
+                    format!("<a href=\"{}\">{}</a>", escape(url.as_str()), name).as_str(),
+                ),
+                None => html.push_str(name.as_str()),
+            }
+            html.push_str("</li>");
+        }
+        html.push_str("</ul>");
+    }
+    html.push_str("</body></html>");
+    html
+}
+
+/// Send a mail through the configured SMTP server.
+async fn send_mail(to: String, subject: String, html: String) -> Result<(), Error> {
+    let config = &CONFIG.email;
+    let host = config
+        .host
+        .as_ref()
+        .ok_or_else(|| Error::Localized("error.no_mail_server", vec![]))?;
+    let mail_error =
+        |e: &dyn std::fmt::Debug| Error::Localized("error.send_mail", vec![format!("{:?}", e)]);
+    let email = EmailBuilder::new()
+        .to(to)
+        .from(config.from.as_ref().unwrap_or(&config.username).as_str())
+        .subject(subject)
+        .html(html)
+        .build()
+        .map_err(|e| mail_error(&e))?;
+    let tls = ClientTlsParameters::new(
+        host.clone(),
+        TlsConnector::new().map_err(|e| mail_error(&e))?,
+    );
+    let security = if config.starttls {
+        ClientSecurity::Required(tls)
+    } else {
+        ClientSecurity::Wrapper(tls)
+    };
+    let client = SmtpClient::new((host.as_str(), config.port), security)
+        .map_err(|e| mail_error(&e))?
+        .credentials(Credentials::new(
+            config.username.clone(),
+            config.password.clone(),
+        ));
+    // lettre is blocking
+    tokio::task::spawn_blocking(move || client.transport().send(email.into()))
+        .await
+        .map_err(|e| mail_error(&e))?
+        .map_err(|e| mail_error(&e))?;
+    Ok(())
+}
+
+/// Mail a confirmation code to an address before it is linked, so the bot
+/// cannot be used to mail others.
+pub async fn send_confirmation(to: &str, code: &str, language: Language) -> Result<(), Error> {
+    let html = format!(
+        "<html><body><p>{}</p></body></html>",
+        escape(trf(language, "email.confirm_body", &[&code]).as_str())
+    );
+    send_mail(
+        to.to_string(),
+        tr(language, "email.confirm_subject").to_string(),
+        html,
+    )
+    .await
+}
+
+async fn save_digest_items(user_id: u32, items: &[EmailItem]) -> Result<(), Error> {
+    let mut conn = CONN.lock().await;
+    let tx = conn.transaction()?;
+    let now = Utc::now().naive_utc();
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT INTO `email_digest` \
+            (`user_id`, `course_name`, `text`, `module_type`, `module_name`, `url`, `created_at`) \
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for item in items {
+            stmt.execute(params![
+                user_id,
+                item.course_name,
+                item.text,
+                item.module_type,
+                item.module_name,
+                item.url,
+                now
+            ])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Deliver the entries, with the generic subject in the language of the user
+/// if `subject` is `None`.
+async fn deliver(
+    user_id: u32,
+    subject: Option<String>,
+    items: Vec<EmailItem>,
+) -> Result<(), Error> {
+    let subject = match subject {
+        Some(s) => s,
+        None => {
+            let language = get_tenant_language(Tenant::Email(user_id), 0).await;
+            tr(language, "email.subject").to_string()
+        }
+    };
+    match get_user_email(user_id).await? {
+        Some((_, true)) => save_digest_items(user_id, items.as_slice()).await,
+        Some((email, false)) => send_mail(email, subject, render_html(items.as_slice())).await,
+        None => Err(Error::Localized(
+            "error.no_email",
+            vec![user_id.to_string()],
+        )),
+    }
+}
+
+fn spawn_deliver(
+    user_id: u32,
+    subject: Option<String>,
+    items: Vec<EmailItem>,
+) -> Result<(), Error> {
+    if CONFIG.email.host.is_none() {
+        return Err(Error::Localized("error.no_mail_server", vec![]));
+    }
+    tokio::spawn(async move {
+        if let Err(e) = deliver(user_id, subject, items).await {
+            log(LogLevel::Error, "email", format!("无法发送邮件 {:#?}", e));
+        }
+    });
+    Ok(())
+}
+
+/// Mail the entries to a user in the background, or keep them for the daily
+/// digest if the user prefers.
+pub fn send_email(user_id: u32, subject: String, items: Vec<EmailItem>) -> Result<(), Error> {
+    spawn_deliver(user_id, Some(subject), items)
+}
+
+pub fn send_message(user_id: u32, text: &str) -> Result<(), Error> {
+    spawn_deliver(
+        user_id,
+        None,
+        vec![EmailItem {
+            text: text.to_string(),
+            ..Default::default()
+        }],
+    )
+}
+
+/// Mail the pending digest entries of every user. Entries are only removed
+/// once the mail is sent.
+async fn send_digests() -> Result<(), Error> {
+    let mut digests: BTreeMap<u32, (String, Language, i64, Vec<EmailItem>)> = BTreeMap::new();
+    {
+        let conn = CONN.lock().await;
+        let mut stmt = conn.prepare_cached(
+            "SELECT `d`.`id`, `d`.`user_id`, `u`.`email`, `d`.`course_name`, `d`.`text`, \
+            `d`.`module_type`, `d`.`module_name`, `d`.`url`, `u`.`language` \
+            FROM `email_digest` AS 'd' INNER JOIN `user` AS 'u' ON `u`.`id` = `d`.`user_id` \
+            WHERE `u`.`email` IS NOT NULL ORDER BY `d`.`id`",
+        )?;
+        let mut rows = stmt.query(params![])?;
+        while let Some(row) = rows.next()? {
+            let digest = digests
+                .entry(row.get(1)?)
+                .or_insert_with(|| (String::new(), CONFIG.language, 0, Vec::new()));
+            digest.0 = row.get(2)?;
+            digest.1 = row
+                .get::<_, Option<String>>(8)?
+                .and_then(|code| Language::from_code(code.as_str()))
+                .unwrap_or(CONFIG.language);
+            digest.2 = row.get(0)?;
+            digest.3.push(EmailItem {
+                course_name: row.get(3)?,
+                text: row.get(4)?,
+                module_type: row.get(5)?,
+                module_name: row.get(6)?,
+                url: row.get(7)?,
+            });
+        }
+    }
+    for (user_id, (email, language, last_id, items)) in digests {
+        let date = Local::now().format(tr(language, "time.date")).to_string();
+        let subject = trf(language, "email.digest_subject", &[&date]);
+        if let Err(e) = send_mail(email, subject, render_html(items.as_slice())).await {
+            log(
+                LogLevel::Error,
+                "email",
+                format!("无法发送用户 {} 的每日摘要 {:#?}", user_id, e),
+            );
+            continue;
+        }
+        CONN.lock().await.execute(
+            "DELETE FROM `email_digest` WHERE `user_id` = ?1 AND `id` <= ?2",
+            params![user_id, last_id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Send the daily digests at `digest_hour` every day.
+pub async fn start_digest_loop() {
+    loop {
+        let now = Local::now();
+        let elapsed = (now.hour() * 3600 + now.minute() * 60 + now.second()) as i64;
+        let secs = match (CONFIG.email.digest_hour as i64 * 3600 - elapsed).rem_euclid(86400) {
+            0 => 86400,
+            secs => secs,
+        };
+        delay_for(Duration::from_secs(secs as u64)).await;
+        if let Err(e) = send_digests().await {
+            log(
+                LogLevel::Error,
+                "email",
+                format!("无法发送每日摘要 {:#?}", e),
+            );
+        }
+    }
+}
+
+#[test]
+fn render_html_test() {
+    let html = render_html(&[
+        EmailItem {
+            course_name: Some("A&B".to_string()),
+            text: "发布了".to_string(),
+            module_type: Some("文件".to_string()),
+            module_name: Some("<讲义>".to_string()),
+            url: Some("https://l.xmu.edu.my/mod/resource/view.php?id=1".to_string()),
+        },
+        EmailItem {
+            text: "通知".to_string(),
+            ..Default::default()
+        },
+    ]);
+    assert_eq!(
+        html,
+        "<html><body><ul><li>通知</li></ul>\
+        <h3>A&amp;B</h3><ul><li>发布了 文件 \
+        <a href=\"https://l.xmu.edu.my/mod/resource/view.php?id=1\">&lt;讲义&gt;</a></li></ul>\
+        </body></html>"
+    );
+}
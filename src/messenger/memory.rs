@@ -0,0 +1,61 @@
+use crate::error::Error;
+use crate::messenger::{LogLevel, Messenger};
+use std::sync::Mutex;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SentMessage {
+    Group(i64, String),
+    Private(i64, String),
+}
+
+/// Messenger keeping sent messages and logs in memory, for tests
+#[derive(Default)]
+pub struct MemoryMessenger {
+    sent: Mutex<Vec<SentMessage>>,
+    logs: Mutex<Vec<(LogLevel, String)>>,
+    /// Whether sending fails, as when the chat server is down
+    failing: bool,
+}
+
+impl MemoryMessenger {
+    /// A messenger failing to send every message
+    pub fn failing() -> MemoryMessenger {
+        MemoryMessenger {
+            failing: true,
+            ..Default::default()
+        }
+    }
+
+    /// Take all messages sent so far.
+    pub fn take(&self) -> Vec<SentMessage> {
+        std::mem::take(&mut *self.sent.lock().unwrap())
+    }
+
+    /// Take the level and tag of all logs written so far.
+    pub fn take_logs(&self) -> Vec<(LogLevel, String)> {
+        std::mem::take(&mut *self.logs.lock().unwrap())
+    }
+
+    fn send(&self, msg: SentMessage) -> Result<(), Error> {
+        if self.failing {
+            return Err(Error::Localized("error.onebot_disconnected", vec![]));
+        }
+        self.sent.lock().unwrap().push(msg);
+        Ok(())
+    }
+}
+
+impl Messenger for MemoryMessenger {
+    fn send_group_msg(&self, group_id: i64, msg: &str) -> Result<(), Error> {
+        self.send(SentMessage::Group(group_id, msg.to_string()))
+    }
+
+    fn send_private_msg(&self, user_id: i64, msg: &str) -> Result<(), Error> {
+        self.send(SentMessage::Private(user_id, msg.to_string()))
+    }
+
+    fn log(&self, level: LogLevel, tag: &str, msg: &str) {
+        println!("[{:?}] {}: {}", level, tag, msg);
+        self.logs.lock().unwrap().push((level, tag.to_string()));
+    }
+}
@@ -0,0 +1,131 @@
+#[cfg(feature = "coolq")]
+mod coolq;
+mod email;
+#[cfg(test)]
+mod memory;
+mod onebot;
+mod telegram;
+mod webhook;
+
+#[cfg(feature = "coolq")]
+pub use crate::messenger::coolq::CoolQMessenger;
+pub use crate::messenger::email::{
+    send_confirmation, send_email, start_digest_loop, EmailConfig, EmailItem,
+};
+#[cfg(test)]
+pub use crate::messenger::memory::{MemoryMessenger, SentMessage};
+pub use crate::messenger::onebot::{serve_onebot, OneBotConfig, OneBotMessenger};
+pub use crate::messenger::telegram::{start_telegram_loop, TelegramConfig};
+pub use crate::messenger::webhook::{send_payload, WebhookConfig};
+
+use crate::error::Error;
+use crate::tenant::Tenant;
+use lazy_static::lazy_static;
+use std::sync::{Arc, RwLock};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogLevel {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+/// A chat backend that notifications and replies are delivered through.
+pub trait Messenger: Send + Sync {
+    fn send_group_msg(&self, group_id: i64, msg: &str) -> Result<(), Error>;
+    fn send_private_msg(&self, user_id: i64, msg: &str) -> Result<(), Error>;
+    fn log(&self, level: LogLevel, tag: &str, msg: &str);
+}
+
+lazy_static! {
+    static ref MESSENGER: RwLock<Option<Arc<dyn Messenger>>> = RwLock::new(None);
+}
+
+/// Set the backend used by the whole bot. Must be called before any message
+/// is sent.
+pub fn set_messenger(messenger: Arc<dyn Messenger>) {
+    *MESSENGER.write().expect("Messenger lock poisoned") = Some(messenger);
+}
+
+pub fn messenger() -> Arc<dyn Messenger> {
+    MESSENGER
+        .read()
+        .expect("Messenger lock poisoned")
+        .clone()
+        .expect("Messenger is not set")
+}
+
+/// Write a log through the messenger, or to stdout if there is none yet.
+pub fn log(level: LogLevel, tag: &str, msg: impl AsRef<str>) {
+    let messenger = MESSENGER.read().expect("Messenger lock poisoned").clone();
+    match messenger {
+        Some(m) => m.log(level, tag, msg.as_ref()),
+        None => println!("[{:?}] {}: {}", level, tag, msg.as_ref()),
+    }
+}
+
+/// Send a message to a tenant. `user_qq` is the receiver of personal
+/// subscriptions.
+pub fn send_to_tenant(tenant: Tenant, user_qq: i64, msg: &str) {
+    send_to_tenant_with(messenger().as_ref(), tenant, user_qq, msg);
+}
+
+/// Send a message to a tenant, through `messenger` for QQ chats. Failures
+/// are logged through `messenger`.
+fn send_to_tenant_with(messenger: &dyn Messenger, tenant: Tenant, user_qq: i64, msg: &str) {
+    let ret = match tenant {
+        Tenant::Group(group_qq) => messenger.send_group_msg(group_qq, msg),
+        Tenant::SenderSelf => messenger.send_private_msg(user_qq, msg),
+        Tenant::Telegram(chat_id) => telegram::send_message(chat_id, msg),
+        Tenant::Webhook(webhook_id) => webhook::send_message(webhook_id, msg),
+        Tenant::Email(user_id) => email::send_message(user_id, msg),
+    };
+    if let Err(e) = ret {
+        messenger.log(
+            LogLevel::Error,
+            "send",
+            format!("无法发送消息\"{}\"到 {:?} 因为 {:#?}", msg, tenant, e).as_str(),
+        );
+    }
+}
+
+#[test]
+fn send_to_tenant_test() {
+    let messenger = MemoryMessenger::default();
+    send_to_tenant_with(&messenger, Tenant::Group(1001), 0, "群消息");
+    send_to_tenant_with(&messenger, Tenant::SenderSelf, 10001, "私聊消息");
+    assert_eq!(
+        messenger.take(),
+        vec![
+            SentMessage::Group(1001, "群消息".to_string()),
+            SentMessage::Private(10001, "私聊消息".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn send_to_tenant_failure_test() {
+    let messenger = MemoryMessenger::failing();
+    send_to_tenant_with(&messenger, Tenant::Group(1001), 0, "群消息");
+    assert!(messenger.take().is_empty());
+    assert_eq!(
+        messenger.take_logs(),
+        vec![(LogLevel::Error, "send".to_string())]
+    );
+}
+
+#[test]
+fn send_to_unconfigured_tenant_test() {
+    // Telegram, webhooks and email are not configured in tests, so nothing
+    // reaches the QQ messenger and each failure is logged
+    let messenger = MemoryMessenger::default();
+    send_to_tenant_with(&messenger, Tenant::Telegram(-1001), 0, "Telegram 消息");
+    send_to_tenant_with(&messenger, Tenant::Webhook(-1), 0, "webhook 消息");
+    send_to_tenant_with(&messenger, Tenant::Email(1), 10001, "邮件消息");
+    assert!(messenger.take().is_empty());
+    assert_eq!(
+        messenger.take_logs(),
+        vec![(LogLevel::Error, "send".to_string()); 3]
+    );
+}
@@ -0,0 +1,168 @@
+use crate::handler::{handle_group_message, handle_leave_group, handle_private_message};
+use crate::messenger::onebot::OneBotMessenger;
+use crate::messenger::{log, LogLevel};
+use crate::permission::Role;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use zeroize::Zeroize;
+
+/// Events reported by the OneBot server. Only the fields the bot needs are
+/// kept.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "post_type", rename_all = "snake_case")]
+pub enum Event {
+    Message(MessageEvent),
+    Notice(NoticeEvent),
+    Request(RequestEvent),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MessageEvent {
+    pub message_type: String,
+    pub self_id: i64,
+    pub user_id: i64,
+    pub group_id: Option<i64>,
+    /// Message in the CQ code format
+    pub raw_message: String,
+    pub sender: Sender,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Sender {
+    #[serde(default)]
+    pub nickname: String,
+    /// "owner", "admin" or "member" in group messages
+    pub role: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NoticeEvent {
+    pub notice_type: String,
+    pub self_id: i64,
+    pub group_id: Option<i64>,
+    pub user_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestEvent {
+    pub request_type: String,
+    pub sub_type: Option<String>,
+    pub flag: String,
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&#91;", "[")
+        .replace("&#93;", "]")
+        .replace("&#44;", ",")
+        .replace("&amp;", "&")
+}
+
+/// Split a message in the CQ code format into its plain text and the QQ
+/// numbers mentioned by `[CQ:at]`.
+pub fn parse_message(raw: &str) -> (String, Vec<i64>) {
+    let mut text = String::new();
+    let mut ats = vec![];
+    let mut rest = raw;
+    while let Some(start) = rest.find("[CQ:") {
+        text.push_str(unescape(&rest[..start]).as_str());
+        let end = match rest[start..].find(']') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let mut parts = rest[start + 4..end].split(',');
+        if parts.next() == Some("at") {
+            ats.extend(
+                parts
+                    .filter_map(|p| p.strip_prefix("qq="))
+                    .filter_map(|qq| qq.parse::<i64>().ok()),
+            );
+        }
+        rest = &rest[end + 1..];
+    }
+    text.push_str(unescape(rest).as_str());
+    (text, ats)
+}
+
+/// Start dispatching the events sent to the returned channel one at a time,
+/// so the commands of a chat are handled in the order they are sent.
+pub fn start_dispatcher(messenger: Arc<OneBotMessenger>) -> UnboundedSender<Event> {
+    let (tx, mut rx) = unbounded_channel();
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            dispatch(event, messenger.clone()).await;
+        }
+    });
+    tx
+}
+
+async fn dispatch(event: Event, messenger: Arc<OneBotMessenger>) {
+    let ret = match event {
+        Event::Message(mut event) => {
+            let (mut text, ats) = parse_message(event.raw_message.as_str());
+            match (event.message_type.as_str(), event.group_id) {
+                ("private", _) => {
+                    handle_private_message(
+                        event.user_id,
+                        event.sender.nickname.as_str(),
+                        text.as_str(),
+                    )
+                    .await
+                }
+                ("group", Some(group_id)) if ats.contains(&event.self_id) => {
+                    let role = match event.sender.role.as_deref() {
+                        Some("owner") => Role::Owner,
+                        Some("admin") => Role::Admin,
+                        _ => Role::Member,
+                    };
+                    handle_group_message(group_id, event.user_id, role, text.as_str()).await
+                }
+                _ => {}
+            }
+            // The message may contain a password
+            text.zeroize();
+            event.raw_message.zeroize();
+            Ok(())
+        }
+        Event::Notice(event) => {
+            match (event.notice_type.as_str(), event.group_id, event.user_id) {
+                // 被踢出群
+                ("group_decrease", Some(group_id), Some(user_id)) if user_id == event.self_id => {
+                    handle_leave_group(group_id, "").await
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+        Event::Request(event) => match (event.request_type.as_str(), event.sub_type.as_deref()) {
+            ("friend", _) => messenger.call(
+                "set_friend_add_request",
+                json!({ "flag": event.flag, "approve": true }),
+            ),
+            ("group", Some("invite")) => messenger.call(
+                "set_group_add_request",
+                json!({ "flag": event.flag, "sub_type": "invite", "approve": true }),
+            ),
+            _ => Ok(()),
+        },
+        Event::Other => Ok(()),
+    };
+    if let Err(e) = ret {
+        log(LogLevel::Error, "onebot", format!("无法处理请求 {:#?}", e));
+    }
+}
+
+#[test]
+fn parse_message_test() {
+    assert_eq!(
+        parse_message("[CQ:at,qq=10001] 订阅 1234"),
+        (" 订阅 1234".to_string(), vec![10001])
+    );
+    assert_eq!(
+        parse_message("登录 a&#91;1&#93;&amp;b [CQ:face,id=1]记住"),
+        ("登录 a[1]&b 记住".to_string(), vec![])
+    );
+}
@@ -0,0 +1,106 @@
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::messenger::onebot::event::{start_dispatcher, Event};
+use crate::messenger::onebot::OneBotMessenger;
+use crate::messenger::{log, LogLevel};
+use hmac::{Hmac, Mac, NewMac};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use sha1::Sha1;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Receive events posted by the OneBot server.
+pub async fn serve(messenger: Arc<OneBotMessenger>) -> Result<(), Error> {
+    let addr: SocketAddr = CONFIG
+        .onebot
+        .listen
+        .parse()
+        .map_err(|e| Error::Localized("error.listen_address", vec![format!("{:?}", e)]))?;
+    // Requests are served concurrently, but their events are dispatched in
+    // order
+    let events = start_dispatcher(messenger);
+    let make_svc = make_service_fn(move |_| {
+        let events = events.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle_request(req, events.clone()))) }
+    });
+    log(
+        LogLevel::Success,
+        "onebot",
+        format!("正在接收 OneBot 事件 http://{}", addr),
+    );
+    Server::bind(&addr)
+        .serve(make_svc)
+        .await
+        .map_err(|e| Error::Localized("error.onebot", vec![format!("{:?}", e)]))
+}
+
+fn response(status: StatusCode) -> Result<Response<Body>, Infallible> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = status;
+    Ok(res)
+}
+
+async fn handle_request(
+    req: Request<Body>,
+    events: UnboundedSender<Event>,
+) -> Result<Response<Body>, Infallible> {
+    let signature = req
+        .headers()
+        .get("X-Signature")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_string());
+    let body = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(body) => body,
+        Err(_) => return response(StatusCode::BAD_REQUEST),
+    };
+    if let Some(secret) = &CONFIG.onebot.secret {
+        if !verify_signature(secret.as_str(), &body, signature.as_deref()) {
+            return response(StatusCode::UNAUTHORIZED);
+        }
+    }
+    match serde_json::from_slice::<Event>(&body) {
+        Ok(event) => {
+            // Only fails after the dispatcher is gone
+            let _ = events.send(event);
+        }
+        Err(e) => log(
+            LogLevel::Error,
+            "onebot",
+            format!("无法解析 OneBot 事件 {:#?}", e),
+        ),
+    }
+    response(StatusCode::NO_CONTENT)
+}
+
+/// Check the `X-Signature: sha1=<hex>` header, an HMAC-SHA1 of the body.
+fn verify_signature(secret: &str, body: &[u8], signature: Option<&str>) -> bool {
+    let expected = match signature
+        .and_then(|s| s.strip_prefix("sha1="))
+        .and_then(|s| hex::decode(s).ok())
+    {
+        Some(expected) => expected,
+        None => return false,
+    };
+    let mut mac = Hmac::<Sha1>::new_varkey(secret.as_bytes()).expect("Invalid HMAC key");
+    mac.update(body);
+    mac.verify(&expected).is_ok()
+}
+
+#[test]
+fn verify_signature_test() {
+    let body = br#"{"post_type":"meta_event"}"#;
+    assert!(verify_signature(
+        "secret",
+        body,
+        Some("sha1=3f2b4c15bf23e0d67d4cacb7f5e3de9e3b188f69")
+    ));
+    assert!(!verify_signature(
+        "secret",
+        body,
+        Some("sha1=0000000000000000000000000000000000000000")
+    ));
+    assert!(!verify_signature("secret", body, None));
+}
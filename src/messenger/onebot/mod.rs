@@ -0,0 +1,155 @@
+mod event;
+mod http;
+mod ws;
+
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::messenger::{log, LogLevel, Messenger};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OneBotMode {
+    /// Accept reverse WebSocket connections from the OneBot server
+    Ws,
+    /// Receive events by HTTP POST and call the HTTP API
+    Http,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct OneBotConfig {
+    pub mode: OneBotMode,
+    /// Address to listen on for the OneBot server
+    pub listen: String,
+    /// Base URL of the OneBot HTTP API, used in `http` mode
+    pub api_url: String,
+    /// Access token configured on the OneBot server
+    pub access_token: Option<String>,
+    /// Secret signing the reported events in `http` mode
+    pub secret: Option<String>,
+}
+
+impl Default for OneBotConfig {
+    fn default() -> Self {
+        OneBotConfig {
+            mode: OneBotMode::Ws,
+            listen: "127.0.0.1:6700".to_string(),
+            api_url: "http://127.0.0.1:5700".to_string(),
+            access_token: None,
+            secret: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse {
+    status: String,
+    retcode: i32,
+    /// Action name echoed back on the reverse WebSocket
+    echo: Option<String>,
+}
+
+/// Messenger of a OneBot v11 server such as go-cqhttp
+#[derive(Default)]
+pub struct OneBotMessenger {
+    /// ID and outgoing frames of the current reverse WebSocket connection
+    ws: Mutex<Option<(u64, UnboundedSender<String>)>>,
+    client: reqwest::Client,
+}
+
+impl OneBotMessenger {
+    fn connect(&self, id: u64, tx: UnboundedSender<String>) {
+        *self.ws.lock().expect("OneBot connection lock poisoned") = Some((id, tx));
+    }
+
+    /// Drop the connection unless a newer one has replaced it.
+    fn disconnect(&self, id: u64) {
+        let mut ws = self.ws.lock().expect("OneBot connection lock poisoned");
+        if matches!(&*ws, Some((current, _)) if *current == id) {
+            *ws = None;
+        }
+    }
+
+    /// Call an API of the OneBot server. The result is only logged when it
+    /// fails.
+    fn call(&self, action: &str, params: Value) -> Result<(), Error> {
+        match CONFIG.onebot.mode {
+            OneBotMode::Ws => {
+                let ws = self.ws.lock().expect("OneBot connection lock poisoned");
+                let (_, tx) = ws
+                    .as_ref()
+                    .ok_or_else(|| Error::Localized("error.onebot_disconnected", vec![]))?;
+                tx.send(json!({ "action": action, "params": params, "echo": action }).to_string())
+                    .map_err(|e| Error::Localized("error.onebot", vec![format!("{:?}", e)]))
+            }
+            OneBotMode::Http => {
+                let url = format!("{}/{}", CONFIG.onebot.api_url.trim_end_matches('/'), action);
+                let mut req = self.client.post(url.as_str()).json(&params);
+                if let Some(token) = &CONFIG.onebot.access_token {
+                    req = req.bearer_auth(token);
+                }
+                let action = action.to_string();
+                tokio::spawn(async move {
+                    match req.send().await {
+                        Ok(res) => match res.json::<ApiResponse>().await {
+                            Ok(res) => check_response(action.as_str(), &res),
+                            Err(e) => log(
+                                LogLevel::Error,
+                                "onebot",
+                                format!("无法解析 {} 的结果 {:#?}", action, e),
+                            ),
+                        },
+                        Err(e) => log(
+                            LogLevel::Error,
+                            "onebot",
+                            format!("无法调用 {} {:#?}", action, e),
+                        ),
+                    }
+                });
+                Ok(())
+            }
+        }
+    }
+}
+
+fn check_response(action: &str, res: &ApiResponse) {
+    if res.status == "failed" {
+        log(
+            LogLevel::Error,
+            "onebot",
+            format!("调用 {} 失败，错误码 {}", action, res.retcode),
+        );
+    }
+}
+
+impl Messenger for OneBotMessenger {
+    fn send_group_msg(&self, group_id: i64, msg: &str) -> Result<(), Error> {
+        self.call(
+            "send_group_msg",
+            json!({ "group_id": group_id, "message": msg, "auto_escape": true }),
+        )
+    }
+
+    fn send_private_msg(&self, user_id: i64, msg: &str) -> Result<(), Error> {
+        self.call(
+            "send_private_msg",
+            json!({ "user_id": user_id, "message": msg, "auto_escape": true }),
+        )
+    }
+
+    fn log(&self, level: LogLevel, tag: &str, msg: &str) {
+        println!("[{:?}] {}: {}", level, tag, msg);
+    }
+}
+
+/// Receive events from the OneBot server until it fails.
+pub async fn serve_onebot(messenger: Arc<OneBotMessenger>) -> Result<(), Error> {
+    match CONFIG.onebot.mode {
+        OneBotMode::Ws => ws::serve(messenger).await,
+        OneBotMode::Http => http::serve(messenger).await,
+    }
+}
@@ -0,0 +1,147 @@
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::messenger::onebot::event::{start_dispatcher, Event};
+use crate::messenger::onebot::{check_response, ApiResponse, OneBotMessenger};
+use crate::messenger::{log, LogLevel};
+use futures::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tokio_tungstenite::accept_hdr_async;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Accept reverse WebSocket connections. Only the latest connection is used
+/// to call APIs.
+pub async fn serve(messenger: Arc<OneBotMessenger>) -> Result<(), Error> {
+    let mut listener = TcpListener::bind(CONFIG.onebot.listen.as_str())
+        .await
+        .map_err(|e| {
+            Error::Localized(
+                "error.listen",
+                vec![CONFIG.onebot.listen.clone(), e.to_string()],
+            )
+        })?;
+    log(
+        LogLevel::Success,
+        "onebot",
+        format!("正在等待 OneBot 连接 ws://{}", CONFIG.onebot.listen),
+    );
+    // Events of all connections are dispatched in order
+    let events = start_dispatcher(messenger.clone());
+    let mut id = 0;
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .map_err(|e| Error::Localized("error.onebot", vec![format!("{:?}", e)]))?;
+        tokio::spawn(handle_connection(
+            id,
+            stream,
+            messenger.clone(),
+            events.clone(),
+        ));
+        id += 1;
+    }
+}
+
+// The error type is required by tungstenite
+#[allow(clippy::result_large_err)]
+fn authorize(req: &Request, res: Response) -> Result<Response, ErrorResponse> {
+    let token = match &CONFIG.onebot.access_token {
+        Some(token) => token,
+        None => return Ok(res),
+    };
+    let authorized = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.trim_start_matches("Bearer ").trim_start_matches("Token "))
+        == Some(token.as_str());
+    if authorized {
+        Ok(res)
+    } else {
+        let mut res = ErrorResponse::new(None);
+        *res.status_mut() = StatusCode::UNAUTHORIZED;
+        Err(res)
+    }
+}
+
+async fn handle_connection(
+    id: u64,
+    stream: TcpStream,
+    messenger: Arc<OneBotMessenger>,
+    events: UnboundedSender<Event>,
+) {
+    let ws = match accept_hdr_async(stream, authorize).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            log(
+                LogLevel::Warning,
+                "onebot",
+                format!("无法接受 OneBot 连接 {:#?}", e),
+            );
+            return;
+        }
+    };
+    log(LogLevel::Info, "onebot", "OneBot 已连接");
+    let (mut sink, mut stream) = ws.split();
+    let (tx, mut rx) = unbounded_channel::<String>();
+    messenger.connect(id, tx);
+    tokio::spawn(async move {
+        while let Some(frame) = rx.recv().await {
+            if sink.send(Message::Text(frame)).await.is_err() {
+                break;
+            }
+        }
+    });
+    while let Some(msg) = stream.next().await {
+        match msg {
+            Ok(Message::Text(text)) => handle_text(text.as_str(), &events),
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                log(
+                    LogLevel::Warning,
+                    "onebot",
+                    format!("OneBot 连接出错 {:#?}", e),
+                );
+                break;
+            }
+        }
+    }
+    messenger.disconnect(id);
+    log(LogLevel::Warning, "onebot", "OneBot 连接已断开");
+}
+
+/// A frame is either an event or the response of an API call.
+fn handle_text(text: &str, events: &UnboundedSender<Event>) {
+    let value: Value = match serde_json::from_str(text) {
+        Ok(v) => v,
+        Err(e) => {
+            log(
+                LogLevel::Error,
+                "onebot",
+                format!("无法解析 OneBot 消息 {:#?}", e),
+            );
+            return;
+        }
+    };
+    if value.get("post_type").is_some() {
+        match serde_json::from_value::<Event>(value) {
+            Ok(event) => {
+                // Only fails after the dispatcher is gone
+                let _ = events.send(event);
+            }
+            Err(e) => log(
+                LogLevel::Error,
+                "onebot",
+                format!("无法解析 OneBot 事件 {:#?}", e),
+            ),
+        }
+    } else if let Ok(res) = serde_json::from_value::<ApiResponse>(value) {
+        check_response(res.echo.as_deref().unwrap_or("API"), &res);
+    }
+}
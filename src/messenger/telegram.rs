@@ -0,0 +1,270 @@
+use crate::command::{find_command, CommandKind};
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::handler::{handle_leave_telegram_chat, handle_telegram_message};
+use crate::messenger::{log, LogLevel};
+use crate::permission::Role;
+use lazy_static::lazy_static;
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::time::{delay_for, Duration};
+use zeroize::Zeroize;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct TelegramConfig {
+    /// Bot token from @BotFather. The Telegram bot is disabled without it.
+    pub token: Option<String>,
+    /// Seconds to wait for new updates in each long poll
+    pub poll_timeout: u64,
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        TelegramConfig {
+            token: None,
+            poll_timeout: 30,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    error_code: Option<u16>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    id: i64,
+    first_name: String,
+    username: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    id: i64,
+    #[serde(rename = "type")]
+    chat_type: String,
+    title: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    message_id: i64,
+    from: Option<User>,
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMember {
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMemberUpdated {
+    chat: Chat,
+    new_chat_member: ChatMember,
+}
+
+#[derive(Debug, Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+    my_chat_member: Option<ChatMemberUpdated>,
+}
+
+lazy_static! {
+    static ref CLIENT: Client = Client::new();
+}
+
+async fn call<T: DeserializeOwned>(method: &str, params: Value) -> Result<T, Error> {
+    let token = CONFIG
+        .telegram
+        .token
+        .as_ref()
+        .ok_or_else(|| Error::Localized("error.no_telegram", vec![]))?;
+    // Request errors contain the URL, and thus the token
+    let req_error = |e: reqwest::Error| {
+        Error::Localized(
+            "error.telegram",
+            vec![format!("{:?}", e).replace(token, "***")],
+        )
+    };
+    let res: ApiResponse<T> = CLIENT
+        .post(format!("https://api.telegram.org/bot{}/{}", token, method).as_str())
+        .json(&params)
+        .send()
+        .await
+        .map_err(req_error)?
+        .json()
+        .await
+        .map_err(req_error)?;
+    match res.result {
+        Some(result) if res.ok => Ok(result),
+        _ if res.error_code == Some(401) => Err(Error::Localized("error.telegram_token", vec![])),
+        _ => Err(Error::Localized(
+            "error.telegram",
+            vec![res.description.unwrap_or_default()],
+        )),
+    }
+}
+
+/// Call a Telegram method in the background. Failures are only logged.
+fn call_later(method: &'static str, params: Value) {
+    tokio::spawn(async move {
+        if let Err(e) = call::<Value>(method, params).await {
+            log(
+                LogLevel::Error,
+                "telegram",
+                format!("无法调用 {} {:#?}", method, e),
+            );
+        }
+    });
+}
+
+/// Role of a user in a group. Members who cannot be looked up are treated as
+/// ordinary members.
+async fn get_member_role(chat_id: i64, user_id: i64) -> Role {
+    let params = json!({ "chat_id": chat_id, "user_id": user_id });
+    match call::<ChatMember>("getChatMember", params).await {
+        Ok(member) => match member.status.as_str() {
+            "creator" => Role::Owner,
+            "administrator" => Role::Admin,
+            _ => Role::Member,
+        },
+        Err(e) => {
+            log(
+                LogLevel::Warning,
+                "telegram",
+                format!("无法获取 {} 在 {} 的身份 {:#?}", user_id, chat_id, e),
+            );
+            Role::Member
+        }
+    }
+}
+
+pub fn send_message(chat_id: i64, text: &str) -> Result<(), Error> {
+    if CONFIG.telegram.token.is_none() {
+        return Err(Error::Localized("error.no_telegram", vec![]));
+    }
+    call_later("sendMessage", json!({ "chat_id": chat_id, "text": text }));
+    Ok(())
+}
+
+/// Receive updates by long polling and handle them, until the bot token is
+/// rejected.
+pub async fn start_telegram_loop() {
+    let me: User = match call("getMe", json!({})).await {
+        Ok(me) => me,
+        Err(e) => {
+            log(
+                LogLevel::Error,
+                "telegram",
+                format!("无法启动 Telegram bot {:#?}", e),
+            );
+            return;
+        }
+    };
+    let mention = format!("@{}", me.username.unwrap_or_default());
+    log(
+        LogLevel::Success,
+        "telegram",
+        format!("Telegram bot {} 已启动", mention),
+    );
+    let mut offset = 0;
+    loop {
+        let params = json!({
+            "offset": offset,
+            "timeout": CONFIG.telegram.poll_timeout,
+            "allowed_updates": ["message", "my_chat_member"],
+        });
+        let updates: Vec<Update> = match call("getUpdates", params).await {
+            Ok(updates) => updates,
+            Err(Error::Localized("error.telegram_token", _)) => {
+                log(
+                    LogLevel::Error,
+                    "telegram",
+                    "Telegram bot 的 token 无效，已停止接收消息",
+                );
+                return;
+            }
+            Err(e) => {
+                log(
+                    LogLevel::Warning,
+                    "telegram",
+                    format!("无法获取 Telegram 消息 {:#?}", e),
+                );
+                delay_for(Duration::from_secs(10)).await;
+                continue;
+            }
+        };
+        for update in updates {
+            offset = offset.max(update.update_id + 1);
+            // Handled in order, so commands of a chat apply as they are sent
+            handle_update(update, mention.clone()).await;
+        }
+    }
+}
+
+async fn handle_update(update: Update, mention: String) {
+    if let Some(member) = update.my_chat_member {
+        let status = member.new_chat_member.status.as_str();
+        if status == "left" || status == "kicked" {
+            handle_leave_telegram_chat(
+                member.chat.id,
+                member.chat.title.unwrap_or_default().as_str(),
+            )
+            .await;
+        }
+        return;
+    }
+    let (message_id, from, chat, mut text) = match update.message {
+        Some(Message {
+            message_id,
+            from: Some(from),
+            chat,
+            text: Some(text),
+        }) => (message_id, from, chat, text),
+        _ => return,
+    };
+    if chat.chat_type == "private" {
+        handle_telegram_message(
+            chat.id,
+            true,
+            from.id,
+            from.first_name.as_str(),
+            Role::Owner,
+            text.as_str(),
+        )
+        .await;
+        // Do not leave the password in the chat history
+        let command = text.split_ascii_whitespace().next().and_then(find_command);
+        if matches!(command, Some(c) if c.kind == CommandKind::Login) {
+            call_later(
+                "deleteMessage",
+                json!({ "chat_id": chat.id, "message_id": message_id }),
+            );
+        }
+    } else if text.contains(mention.as_str()) {
+        let mut msg = text.replace(mention.as_str(), " ");
+        let role = get_member_role(chat.id, from.id).await;
+        handle_telegram_message(
+            chat.id,
+            false,
+            from.id,
+            from.first_name.as_str(),
+            role,
+            msg.as_str(),
+        )
+        .await;
+        msg.zeroize();
+    }
+    // The message may contain a password
+    text.zeroize();
+}
@@ -0,0 +1,118 @@
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::locale::Language;
+use crate::messenger::{log, LogLevel};
+use hmac::{Hmac, Mac, NewMac};
+use lazy_static::lazy_static;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha1::Sha1;
+use tokio::time::{delay_for, Duration};
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// URL receiving the notifications as JSON POSTs
+    pub url: String,
+    /// Secret signing the body in the `X-Signature` header
+    pub secret: Option<String>,
+    /// Times to retry a failed delivery, waiting twice as long each time
+    pub retries: u32,
+    /// Language of the notifications, the default one if not set
+    pub language: Option<Language>,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            url: String::new(),
+            secret: None,
+            retries: 3,
+            language: None,
+        }
+    }
+}
+
+lazy_static! {
+    static ref CLIENT: Client = Client::new();
+}
+
+/// `sha1=<hex>` of the HMAC-SHA1 of the body, the same format OneBot uses.
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha1>::new_varkey(secret.as_bytes()).expect("Invalid HMAC key");
+    mac.update(body);
+    format!("sha1={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn post(url: &str, body: String, signature: Option<&str>) -> Result<(), String> {
+    let mut req = CLIENT
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(body);
+    if let Some(signature) = signature {
+        req = req.header("X-Signature", signature);
+    }
+    let res = req.send().await.map_err(|e| format!("{:?}", e))?;
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("HTTP {}", res.status()))
+    }
+}
+
+/// POST a payload to a webhook in the background, retrying on failure. The
+/// webhook ID is added to the payload.
+pub fn send_payload(webhook_id: i64, mut payload: Value) -> Result<(), Error> {
+    let webhook = CONFIG
+        .webhooks
+        .get(&webhook_id)
+        .ok_or_else(|| Error::Localized("error.no_webhook", vec![webhook_id.to_string()]))?;
+    payload["webhook"] = json!(webhook_id);
+    let body = payload.to_string();
+    let signature = webhook
+        .secret
+        .as_ref()
+        .map(|secret| sign(secret.as_str(), body.as_bytes()));
+    tokio::spawn(async move {
+        let mut delay = Duration::from_secs(5);
+        let mut attempt = 0;
+        loop {
+            let e = match post(webhook.url.as_str(), body.clone(), signature.as_deref()).await {
+                Ok(()) => return,
+                Err(e) => e,
+            };
+            if attempt >= webhook.retries {
+                log(
+                    LogLevel::Error,
+                    "webhook",
+                    format!("无法发送到 webhook {}，已放弃 {}", webhook_id, e),
+                );
+                return;
+            }
+            log(
+                LogLevel::Warning,
+                "webhook",
+                format!("无法发送到 webhook {}，稍后重试 {}", webhook_id, e),
+            );
+            delay_for(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    });
+    Ok(())
+}
+
+/// Deliver a plain text message, such as a reply or a reminder.
+pub fn send_message(webhook_id: i64, text: &str) -> Result<(), Error> {
+    send_payload(webhook_id, json!({ "type": "message", "text": text }))
+}
+
+#[test]
+fn sign_test() {
+    assert_eq!(
+        sign("secret", br#"{"post_type":"meta_event"}"#),
+        "sha1=3f2b4c15bf23e0d67d4cacb7f5e3de9e3b188f69"
+    );
+}
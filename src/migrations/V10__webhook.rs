@@ -0,0 +1,14 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+    m.create_table("user_course_webhook", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("user_id", types::integer().indexed(true));
+        t.add_column("course_id", types::integer().indexed(true));
+        t.add_column("webhook_id", types::integer().indexed(true));
+        t.add_column("failure_count", types::integer().default(0));
+        t.add_column("created_at", types::date());
+    });
+    m.make::<Sqlite>()
+}
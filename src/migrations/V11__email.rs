@@ -0,0 +1,33 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    // SQLite can only add one column in each ALTER TABLE
+    m.change_table("user", |t| {
+        t.add_column("email", types::text().nullable(true));
+    });
+    m.change_table("user", |t| {
+        t.add_column("email_digest", types::boolean().default(0));
+    });
+    m.create_table("user_course_email", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("user_id", types::integer().indexed(true));
+        t.add_column("course_id", types::integer().indexed(true));
+        t.add_column("failure_count", types::integer().default(0));
+        t.add_column("created_at", types::date());
+    });
+    // Pending entries of daily digests
+    m.create_table("email_digest", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("user_id", types::integer().indexed(true));
+        t.add_column("course_name", types::text().nullable(true));
+        t.add_column("text", types::text());
+        t.add_column("module_type", types::text().nullable(true));
+        t.add_column("module_name", types::text().nullable(true));
+        t.add_column("url", types::text().nullable(true));
+        t.add_column("created_at", types::date());
+    });
+
+    m.make::<Sqlite>()
+}
@@ -0,0 +1,29 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+    m.create_table("group_policy", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("tenant_type", types::varchar(15));
+        t.add_column("tenant_id", types::integer());
+        t.add_column("policy", types::varchar(15));
+        t.add_column("updated_at", types::date());
+    });
+    m.create_table("audit_log", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("user_id", types::integer().indexed(true));
+        t.add_column("tenant_type", types::varchar(15));
+        t.add_column("tenant_id", types::integer());
+        t.add_column("action", types::varchar(31));
+        t.add_column("course_id", types::integer().nullable(true));
+        t.add_column("created_at", types::date());
+    });
+
+    // barrel cannot create indices on multiple columns
+    format!(
+        "{}\n\
+        CREATE UNIQUE INDEX `group_policy_tenant` ON `group_policy` (`tenant_type`, `tenant_id`);\n\
+        CREATE INDEX `audit_log_tenant` ON `audit_log` (`tenant_type`, `tenant_id`);",
+        m.make::<Sqlite>()
+    )
+}
@@ -0,0 +1,11 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    m.change_table("user", |t| {
+        t.add_column("banned", types::boolean().default(0));
+    });
+
+    m.make::<Sqlite>()
+}
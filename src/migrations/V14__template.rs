@@ -0,0 +1,21 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+    m.create_table("notification_template", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("tenant_type", types::varchar(15));
+        t.add_column("tenant_id", types::integer());
+        t.add_column("kind", types::varchar(31));
+        t.add_column("template", types::text());
+        t.add_column("updated_at", types::date());
+    });
+
+    // barrel cannot create indices on multiple columns
+    format!(
+        "{}\n\
+        CREATE UNIQUE INDEX `notification_template_tenant_kind` \
+        ON `notification_template` (`tenant_type`, `tenant_id`, `kind`);",
+        m.make::<Sqlite>()
+    )
+}
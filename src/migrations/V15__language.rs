@@ -0,0 +1,22 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+    m.change_table("user", |t| {
+        t.add_column("language", types::varchar(15).nullable(true));
+    });
+    m.create_table("chat_language", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("tenant_type", types::varchar(15));
+        t.add_column("tenant_id", types::integer());
+        t.add_column("language", types::varchar(15));
+        t.add_column("updated_at", types::date());
+    });
+
+    // barrel cannot create indices on multiple columns
+    format!(
+        "{}\n\
+        CREATE UNIQUE INDEX `chat_language_tenant` ON `chat_language` (`tenant_type`, `tenant_id`);",
+        m.make::<Sqlite>()
+    )
+}
@@ -0,0 +1,11 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    m.change_table("user_course_module", |t| {
+        t.add_column("content_hash", types::varchar(40).nullable(true));
+    });
+
+    m.make::<Sqlite>()
+}
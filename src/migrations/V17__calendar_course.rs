@@ -0,0 +1,13 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    m.create_table("calendar_course", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("course_id", types::integer().unique(true));
+        t.add_column("created_at", types::date());
+    });
+
+    m.make::<Sqlite>()
+}
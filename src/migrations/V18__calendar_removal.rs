@@ -0,0 +1,19 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    m.change_table("calendar_event", |t| {
+        t.add_column("module_name", types::varchar(31).nullable(true));
+    });
+    m.change_table("calendar_course", |t| {
+        t.add_column("checked_at", types::integer().nullable(true));
+    });
+
+    // Events modified after the upgrade are announced as new
+    format!(
+        "{}\n\
+        UPDATE `calendar_course` SET `checked_at` = strftime('%s', 'now');",
+        m.make::<Sqlite>()
+    )
+}
@@ -0,0 +1,11 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    m.change_table("user_course_self", |t| {
+        t.add_column("failure_count", types::integer().default(0));
+    });
+
+    m.make::<Sqlite>()
+}
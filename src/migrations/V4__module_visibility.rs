@@ -0,0 +1,18 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    // SQLite can only add one column in each ALTER TABLE
+    m.change_table("user_course_module", |t| {
+        t.add_column("module_name", types::text().nullable(true));
+    });
+    m.change_table("user_course_module", |t| {
+        t.add_column("visible", types::boolean().nullable(true));
+    });
+    m.change_table("user_course_module", |t| {
+        t.add_column("removed", types::boolean().default(0));
+    });
+
+    m.make::<Sqlite>()
+}
@@ -0,0 +1,28 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    m.create_table("assignment", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("assignment_id", types::integer().unique(true));
+        t.add_column("course_id", types::integer().indexed(true));
+        t.add_column("module_id", types::integer());
+        t.add_column("course_name", types::text());
+        t.add_column("name", types::text());
+        t.add_column("intro", types::text());
+        t.add_column("due_date", types::integer());
+        t.add_column("cut_off_date", types::integer());
+        t.add_column("updated_at", types::date());
+    });
+
+    m.create_table("assignment_reminder", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("assignment_id", types::integer().indexed(true));
+        t.add_column("due_date", types::integer());
+        t.add_column("offset", types::integer());
+        t.add_column("created_at", types::date());
+    });
+
+    m.make::<Sqlite>()
+}
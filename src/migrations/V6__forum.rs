@@ -0,0 +1,21 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    m.create_table("forum", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("forum_id", types::integer().unique(true));
+        t.add_column("course_id", types::integer().indexed(true));
+        t.add_column("created_at", types::date());
+    });
+
+    m.create_table("forum_discussion", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("discussion_id", types::integer().unique(true));
+        t.add_column("forum_id", types::integer().indexed(true));
+        t.add_column("created_at", types::date());
+    });
+
+    m.make::<Sqlite>()
+}
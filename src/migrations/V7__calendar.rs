@@ -0,0 +1,24 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    m.create_table("course", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("course_id", types::integer().unique(true));
+        t.add_column("name", types::text());
+        t.add_column("updated_at", types::date());
+    });
+
+    m.create_table("calendar_event", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("event_id", types::integer().unique(true));
+        t.add_column("course_id", types::integer().indexed(true));
+        t.add_column("name", types::text());
+        t.add_column("time_start", types::integer().indexed(true));
+        t.add_column("time_duration", types::integer());
+        t.add_column("updated_at", types::date());
+    });
+
+    m.make::<Sqlite>()
+}
@@ -0,0 +1,15 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    let mut m = Migration::new();
+
+    // SQLite can only add one column in each ALTER TABLE
+    m.change_table("user", |t| {
+        t.add_column("token_expired", types::boolean().default(0));
+    });
+    m.change_table("user", |t| {
+        t.add_column("credential", types::binary().nullable(true));
+    });
+
+    m.make::<Sqlite>()
+}
@@ -0,0 +1,39 @@
+use barrel::{backend::Sqlite, types, Migration};
+
+pub fn migration() -> String {
+    // Users from Telegram have no QQ. SQLite cannot drop NOT NULL from a
+    // column, so the table is rebuilt.
+    let mut create = Migration::new();
+    create.create_table("user_new", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("qq", types::integer().unique(true).nullable(true));
+        t.add_column("telegram_id", types::integer().unique(true).nullable(true));
+        t.add_column("nickname", types::text());
+        t.add_column("moodle_token", types::varchar(63));
+        t.add_column("token_expired", types::boolean().default(0));
+        t.add_column("credential", types::binary().nullable(true));
+    });
+
+    let copy = "INSERT INTO `user_new` \
+        (`id`, `qq`, `nickname`, `moodle_token`, `token_expired`, `credential`) \
+        SELECT `id`, `qq`, `nickname`, `moodle_token`, `token_expired`, `credential` FROM `user`;";
+
+    let mut m = Migration::new();
+    m.drop_table("user");
+    m.rename_table("user_new", "user");
+    m.create_table("user_course_telegram", |t| {
+        t.add_column("id", types::integer().primary(true));
+        t.add_column("user_id", types::integer().indexed(true));
+        t.add_column("course_id", types::integer().indexed(true));
+        t.add_column("chat_id", types::integer().indexed(true));
+        t.add_column("failure_count", types::integer().default(0));
+        t.add_column("created_at", types::date());
+    });
+
+    format!(
+        "{}\n{}\n{}",
+        create.make::<Sqlite>(),
+        copy,
+        m.make::<Sqlite>()
+    )
+}
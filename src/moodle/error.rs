@@ -7,6 +7,13 @@ pub enum Error {
     Req(reqwest::Error),
 }
 
+impl Error {
+    /// Whether the Moodle token is no longer accepted
+    pub fn is_invalid_token(&self) -> bool {
+        matches!(self, Error::Moodle(e) if e.error_code == "invalidtoken")
+    }
+}
+
 impl From<MoodleError> for Error {
     fn from(e: MoodleError) -> Self {
         Error::Moodle(e)
@@ -2,7 +2,10 @@ mod error;
 mod response;
 
 pub use crate::moodle::error::Error;
-pub use crate::moodle::response::{CourseModule, CourseSection, ModuleType};
+pub use crate::moodle::response::{
+    Assignment, CalendarEvent, CalendarEvents, CourseAssignments, CourseModule, CourseSearchResult,
+    CourseSection, CourseSummary, Discussion, Forum, ForumDiscussions, ModuleType, SiteInfo,
+};
 
 use crate::moodle::response::{CoursesPublicInformation, LoginResult, MoodleError, Response};
 use lazy_static::lazy_static;
@@ -15,8 +18,17 @@ lazy_static! {
 
 const LOGIN_URL: &'static str = "https://l.xmu.edu.my/login/token.php?service=moodle_mobile_app";
 const API_URL: &'static str = "https://l.xmu.edu.my/webservice/rest/server.php";
+const SITE_URL: &'static str = "https://l.xmu.edu.my";
+
+/// Link to a module in the Moodle web interface, or to its course if the
+/// module type is unknown.
+pub fn module_url(course_id: u32, module: &CourseModule) -> String {
+    match module.content.modname() {
+        Some(modname) => format!("{}/mod/{}/view.php?id={}", SITE_URL, modname, module.id),
+        None => format!("{}/course/view.php?id={}", SITE_URL, course_id),
+    }
+}
 
-#[allow(unused)]
 pub async fn login<T: Serialize>(username: T, password: T) -> Result<LoginResult, Error> {
     Ok(Into::<Result<LoginResult, MoodleError>>::into(
         CLIENT
@@ -71,6 +83,175 @@ pub async fn get_course_public_information(
     )?)
 }
 
+pub async fn get_site_info(token: impl AsRef<str>) -> Result<SiteInfo, Error> {
+    Ok(Into::<Result<SiteInfo, MoodleError>>::into(
+        CLIENT
+            .post(API_URL)
+            .query(&[
+                ("wsfunction", "core_webservice_get_site_info"),
+                ("wstoken", token.as_ref()),
+                ("moodlewsrestformat", "json"),
+            ])
+            .send()
+            .await?
+            .json::<Response<SiteInfo>>()
+            .await?,
+    )?)
+}
+
+/// First page of the courses matching a search text
+pub async fn search_courses(
+    token: impl AsRef<str>,
+    text: &str,
+) -> Result<CourseSearchResult, Error> {
+    Ok(Into::<Result<CourseSearchResult, MoodleError>>::into(
+        CLIENT
+            .post(API_URL)
+            .query(&[
+                ("wsfunction", "core_course_search_courses"),
+                ("wstoken", token.as_ref()),
+                ("moodlewsrestformat", "json"),
+            ])
+            .form(&[
+                ("criterianame", "search"),
+                ("criteriavalue", text),
+                ("page", "0"),
+                ("perpage", "20"),
+            ])
+            .send()
+            .await?
+            .json::<Response<CourseSearchResult>>()
+            .await?,
+    )?)
+}
+
+/// Courses the user is enrolled in
+pub async fn get_user_courses(
+    token: impl AsRef<str>,
+    user_id: u32,
+) -> Result<Vec<CourseSummary>, Error> {
+    Ok(Into::<Result<Vec<CourseSummary>, MoodleError>>::into(
+        CLIENT
+            .post(API_URL)
+            .query(&[
+                ("wsfunction", "core_enrol_get_users_courses"),
+                ("wstoken", token.as_ref()),
+                ("moodlewsrestformat", "json"),
+            ])
+            .form(&[("userid", user_id)])
+            .send()
+            .await?
+            .json::<Response<Vec<CourseSummary>>>()
+            .await?,
+    )?)
+}
+
+pub async fn get_assignments(
+    token: impl AsRef<str>,
+    course_ids: &[u32],
+) -> Result<CourseAssignments, Error> {
+    let form: Vec<_> = course_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (format!("courseids[{}]", i), id.to_string()))
+        .collect();
+    Ok(Into::<Result<CourseAssignments, MoodleError>>::into(
+        CLIENT
+            .post(API_URL)
+            .query(&[
+                ("wsfunction", "mod_assign_get_assignments"),
+                ("wstoken", token.as_ref()),
+                ("moodlewsrestformat", "json"),
+            ])
+            .form(&form)
+            .send()
+            .await?
+            .json::<Response<CourseAssignments>>()
+            .await?,
+    )?)
+}
+
+pub async fn get_forums(token: impl AsRef<str>, course_ids: &[u32]) -> Result<Vec<Forum>, Error> {
+    let form: Vec<_> = course_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (format!("courseids[{}]", i), id.to_string()))
+        .collect();
+    Ok(Into::<Result<Vec<Forum>, MoodleError>>::into(
+        CLIENT
+            .post(API_URL)
+            .query(&[
+                ("wsfunction", "mod_forum_get_forums_by_courses"),
+                ("wstoken", token.as_ref()),
+                ("moodlewsrestformat", "json"),
+            ])
+            .form(&form)
+            .send()
+            .await?
+            .json::<Response<Vec<Forum>>>()
+            .await?,
+    )?)
+}
+
+/// First page of the discussions in a forum, latest first
+pub async fn get_forum_discussions(
+    token: impl AsRef<str>,
+    forum_id: u32,
+) -> Result<ForumDiscussions, Error> {
+    Ok(Into::<Result<ForumDiscussions, MoodleError>>::into(
+        CLIENT
+            .post(API_URL)
+            .query(&[
+                ("wsfunction", "mod_forum_get_forum_discussions"),
+                ("wstoken", token.as_ref()),
+                ("moodlewsrestformat", "json"),
+            ])
+            .form(&[
+                ("forumid", forum_id.to_string().as_str()),
+                ("page", "0"),
+                ("perpage", "10"),
+            ])
+            .send()
+            .await?
+            .json::<Response<ForumDiscussions>>()
+            .await?,
+    )?)
+}
+
+/// Course events starting in `[time_start, time_end)`
+pub async fn get_calendar_events(
+    token: impl AsRef<str>,
+    course_ids: &[u32],
+    time_start: i64,
+    time_end: i64,
+) -> Result<CalendarEvents, Error> {
+    let mut form: Vec<_> = course_ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| (format!("events[courseids][{}]", i), id.to_string()))
+        .collect();
+    form.extend(vec![
+        ("options[userevents]".to_string(), "0".to_string()),
+        ("options[siteevents]".to_string(), "0".to_string()),
+        ("options[timestart]".to_string(), time_start.to_string()),
+        ("options[timeend]".to_string(), time_end.to_string()),
+    ]);
+    Ok(Into::<Result<CalendarEvents, MoodleError>>::into(
+        CLIENT
+            .post(API_URL)
+            .query(&[
+                ("wsfunction", "core_calendar_get_calendar_events"),
+                ("wstoken", token.as_ref()),
+                ("moodlewsrestformat", "json"),
+            ])
+            .form(&form)
+            .send()
+            .await?
+            .json::<Response<CalendarEvents>>()
+            .await?,
+    )?)
+}
+
 #[tokio::test]
 async fn get_course_content_test() {
     let token = login(env!("CQMS_CAMPUS_ID"), env!("CQMS_CAMPUS_PASSWORD"))
@@ -96,3 +277,74 @@ async fn get_course_public_information_test() {
             .unwrap()
     );
 }
+
+#[tokio::test]
+async fn get_user_courses_test() {
+    let token = login(env!("CQMS_CAMPUS_ID"), env!("CQMS_CAMPUS_PASSWORD"))
+        .await
+        .unwrap()
+        .token;
+    let site_info = get_site_info(&token).await.unwrap();
+    let courses = get_user_courses(&token, site_info.user_id).await.unwrap();
+    println!("{:#?}", courses);
+    if let Some(course) = courses.first() {
+        println!(
+            "{:#?}",
+            search_courses(&token, course.short_name.as_str())
+                .await
+                .unwrap()
+        );
+    }
+}
+
+#[tokio::test]
+async fn get_assignments_test() {
+    let token = login(env!("CQMS_CAMPUS_ID"), env!("CQMS_CAMPUS_PASSWORD"))
+        .await
+        .unwrap()
+        .token;
+    println!(
+        "{:#?}",
+        get_assignments(token, &[env!("CQMS_COURSE_ID").parse().unwrap()])
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn get_forum_discussions_test() {
+    let token = login(env!("CQMS_CAMPUS_ID"), env!("CQMS_CAMPUS_PASSWORD"))
+        .await
+        .unwrap()
+        .token;
+    let forums = get_forums(&token, &[env!("CQMS_COURSE_ID").parse().unwrap()])
+        .await
+        .unwrap();
+    println!("{:#?}", forums);
+    for forum in forums {
+        println!(
+            "{:#?}",
+            get_forum_discussions(&token, forum.id).await.unwrap()
+        );
+    }
+}
+
+#[tokio::test]
+async fn get_calendar_events_test() {
+    let token = login(env!("CQMS_CAMPUS_ID"), env!("CQMS_CAMPUS_PASSWORD"))
+        .await
+        .unwrap()
+        .token;
+    let now = chrono::Utc::now().timestamp();
+    println!(
+        "{:#?}",
+        get_calendar_events(
+            token,
+            &[env!("CQMS_COURSE_ID").parse().unwrap()],
+            now,
+            now + 60 * 60 * 24 * 30
+        )
+        .await
+        .unwrap()
+    );
+}
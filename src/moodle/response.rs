@@ -1,5 +1,6 @@
 use serde::export::Formatter;
 use serde::Deserialize;
+use sha1::{Digest, Sha1};
 use std::error::Error;
 use std::fmt::{self, Display};
 
@@ -45,8 +46,6 @@ impl<T: std::fmt::Debug> Into<Result<T, MoodleError>> for Response<T> {
 #[derive(Debug, Clone, Deserialize)]
 pub struct LoginResult {
     pub token: String,
-    #[serde(rename = "privatetoken")]
-    pub private_token: String,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -73,6 +72,7 @@ pub enum ModuleType {
     Resource {
         #[serde(rename = "contentsinfo")]
         info: Option<ResourceInfo>,
+        contents: Option<Vec<Content>>,
     },
     #[serde(rename = "mediasite")]
     Mediasite,
@@ -81,13 +81,70 @@ pub enum ModuleType {
     #[serde(rename = "folder")]
     Folder { contents: Option<Vec<Content>> },
     #[serde(rename = "page")]
-    Page,
+    Page { contents: Option<Vec<Content>> },
     #[serde(rename = "assign")]
     Assignment,
+    #[serde(rename = "forum")]
+    Forum,
     #[serde(other)]
     Other,
 }
 
+impl ModuleType {
+    /// `modname` in Moodle, unless unknown
+    pub fn modname(&self) -> Option<&'static str> {
+        Some(match self {
+            ModuleType::Resource { .. } => "resource",
+            ModuleType::Mediasite => "mediasite",
+            ModuleType::Url { .. } => "url",
+            ModuleType::Folder { .. } => "folder",
+            ModuleType::Page { .. } => "page",
+            ModuleType::Assignment => "assign",
+            ModuleType::Forum => "forum",
+            ModuleType::Other => return None,
+        })
+    }
+
+    /// Latest modification timestamp reported by Moodle, or 0 if the module
+    /// type carries none.
+    pub fn last_modified(&self) -> i32 {
+        match self {
+            ModuleType::Resource { info, .. } => info.as_ref().map(|i| i.last_modified),
+            ModuleType::Url { contents }
+            | ModuleType::Folder { contents }
+            | ModuleType::Page { contents } => contents
+                .as_ref()
+                .and_then(|c| c.iter().map(|c| c.last_modified).max()),
+            _ => None,
+        }
+        .unwrap_or(0)
+    }
+
+    /// Hex SHA-1 of the names, URLs and timestamps of the files, regardless
+    /// of their order. Catches files added with an older timestamp and links
+    /// changed in place, which `last_modified` misses.
+    pub fn content_hash(&self) -> Option<String> {
+        let contents = match self {
+            ModuleType::Resource { contents, .. }
+            | ModuleType::Url { contents }
+            | ModuleType::Folder { contents }
+            | ModuleType::Page { contents } => contents.as_ref()?,
+            _ => return None,
+        };
+        let mut entries: Vec<_> = contents
+            .iter()
+            .map(|c| format!("{}\0{}\0{}", c.name, c.url, c.last_modified))
+            .collect();
+        entries.sort();
+        let mut hasher = Sha1::new();
+        for entry in entries {
+            hasher.update(entry.as_bytes());
+            hasher.update(b"\n");
+        }
+        Some(hex::encode(hasher.finalize()))
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct CourseModule {
     #[serde(default)]
@@ -120,3 +177,132 @@ pub struct CoursePublicInformation {
     #[serde(rename = "displayname")]
     pub display_name: String,
 }
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SiteInfo {
+    #[serde(rename = "userid")]
+    pub user_id: u32,
+}
+
+/// A course as returned by course searches and enrolment lists
+#[derive(Debug, Clone, Deserialize)]
+pub struct CourseSummary {
+    pub id: u32,
+    #[serde(rename = "shortname")]
+    pub short_name: String,
+    #[serde(rename = "fullname")]
+    pub full_name: String,
+    /// Unix timestamp, 0 if not set
+    #[serde(rename = "startdate", default)]
+    pub start_date: i64,
+    /// Unix timestamp, 0 if not set
+    #[serde(rename = "enddate", default)]
+    pub end_date: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CourseSearchResult {
+    pub courses: Vec<CourseSummary>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CourseAssignments {
+    pub courses: Vec<AssignmentCourse>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssignmentCourse {
+    pub id: u32,
+    #[serde(rename = "fullname")]
+    pub full_name: String,
+    pub assignments: Vec<Assignment>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Assignment {
+    pub id: u32,
+    #[serde(rename = "cmid")]
+    pub module_id: u32,
+    pub name: String,
+    /// 0 if there is no due date
+    #[serde(rename = "duedate")]
+    pub due_date: i64,
+    /// 0 if there is no cut-off date
+    #[serde(rename = "cutoffdate")]
+    pub cut_off_date: i64,
+    #[serde(default)]
+    pub intro: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Forum {
+    pub id: u32,
+    #[serde(rename = "course")]
+    pub course_id: u32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ForumDiscussions {
+    pub discussions: Vec<Discussion>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Discussion {
+    #[serde(rename = "discussion")]
+    pub id: u32,
+    pub subject: String,
+    pub message: String,
+    #[serde(rename = "userfullname")]
+    pub author: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CalendarEvents {
+    pub events: Vec<CalendarEvent>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CalendarEvent {
+    pub id: u32,
+    pub name: String,
+    #[serde(rename = "courseid")]
+    pub course_id: u32,
+    #[serde(rename = "modulename")]
+    pub module_name: Option<String>,
+    #[serde(rename = "timestart")]
+    pub time_start: i64,
+    #[serde(rename = "timeduration")]
+    pub time_duration: i64,
+    #[serde(rename = "timemodified", default)]
+    pub time_modified: i64,
+}
+
+#[test]
+fn content_hash_test() {
+    let content = |name: &str, last_modified| Content {
+        url: format!("https://moodle.example/{}", name),
+        name: name.to_string(),
+        last_modified,
+    };
+    let folder = |contents| ModuleType::Folder {
+        contents: Some(contents),
+    };
+    let hash = folder(vec![content("a.pdf", 100), content("b.pdf", 200)]).content_hash();
+    assert!(hash.is_some());
+    assert_eq!(
+        folder(vec![content("b.pdf", 200), content("a.pdf", 100)]).content_hash(),
+        hash
+    );
+    // Same latest timestamp, but a new file
+    assert_ne!(
+        folder(vec![
+            content("a.pdf", 100),
+            content("b.pdf", 200),
+            content("c.pdf", 50)
+        ])
+        .content_hash(),
+        hash
+    );
+    assert_eq!(ModuleType::Assignment.content_hash(), None);
+}
@@ -0,0 +1,235 @@
+use crate::config::CONFIG;
+use crate::error::Error;
+use crate::locale::Language;
+use crate::template::TemplateKind;
+use crate::tenant::Tenant;
+use crate::user::Account;
+use crate::CONN;
+use chrono::{NaiveDateTime, Utc};
+use rusqlite::{params, OptionalExtension};
+use serde::Deserialize;
+
+/// Role of the sender in the chat a command is sent in. Private chats are
+/// owned by the sender.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Member,
+    Admin,
+    Owner,
+    /// A superuser of the bot, who owns every chat and the webhooks
+    Superuser,
+}
+
+/// Who may change the subscriptions of a group
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupPolicy {
+    /// Every member who has logged in
+    Anyone,
+    /// The owner and admins of the group
+    Admin,
+}
+
+impl GroupPolicy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            GroupPolicy::Anyone => "anyone",
+            GroupPolicy::Admin => "admin",
+        }
+    }
+
+    fn from_str(s: &str) -> Option<GroupPolicy> {
+        match s {
+            "anyone" => Some(GroupPolicy::Anyone),
+            "admin" => Some(GroupPolicy::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SuperUsers {
+    pub qq: Vec<i64>,
+    pub telegram: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct PermissionConfig {
+    /// Accounts allowed to manage every group and the bot itself
+    pub superusers: SuperUsers,
+    /// Policy of the groups that have not set one
+    pub default_group_policy: GroupPolicy,
+}
+
+impl Default for PermissionConfig {
+    fn default() -> Self {
+        PermissionConfig {
+            superusers: SuperUsers::default(),
+            default_group_policy: GroupPolicy::Admin,
+        }
+    }
+}
+
+pub fn is_superuser(account: Account) -> bool {
+    let superusers = &CONFIG.permission.superusers;
+    match account {
+        Account::Qq(qq) => superusers.qq.contains(&qq),
+        Account::Telegram(id) => superusers.telegram.contains(&id),
+    }
+}
+
+pub async fn get_group_policy(tenant: Tenant) -> Result<GroupPolicy, Error> {
+    let (tenant_type, tenant_id) = tenant.key(0);
+    let policy: Option<String> = CONN
+        .lock()
+        .await
+        .query_row(
+            "SELECT `policy` FROM `group_policy` WHERE `tenant_type` = ?1 AND `tenant_id` = ?2",
+            params![tenant_type, tenant_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(policy
+        .as_deref()
+        .and_then(GroupPolicy::from_str)
+        .unwrap_or(CONFIG.permission.default_group_policy))
+}
+
+pub async fn set_group_policy(tenant: Tenant, policy: GroupPolicy) -> Result<(), Error> {
+    let (tenant_type, tenant_id) = tenant.key(0);
+    CONN.lock().await.execute(
+        "INSERT OR REPLACE INTO `group_policy` (`tenant_type`, `tenant_id`, `policy`, `updated_at`) \
+        VALUES (?1, ?2, ?3, ?4)",
+        params![tenant_type, tenant_id, policy.as_str(), Utc::now().naive_utc()],
+    )?;
+    Ok(())
+}
+
+/// A change made to the subscriptions or settings of a tenant
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Subscribe(u32),
+    Unsubscribe(u32),
+    SetPolicy(GroupPolicy),
+    /// Customize or restore a notification template
+    SetTemplate(TemplateKind),
+    /// Choose the language of a chat, or follow the default with `None`
+    SetLanguage(Option<Language>),
+}
+
+impl AuditAction {
+    fn to_row(self) -> (String, Option<u32>) {
+        match self {
+            AuditAction::Subscribe(course_id) => ("subscribe".to_string(), Some(course_id)),
+            AuditAction::Unsubscribe(course_id) => ("unsubscribe".to_string(), Some(course_id)),
+            AuditAction::SetPolicy(policy) => (format!("policy_{}", policy.as_str()), None),
+            AuditAction::SetTemplate(kind) => (format!("template_{}", kind.key()), None),
+            AuditAction::SetLanguage(language) => (
+                format!("language_{}", language.map_or("default", Language::code)),
+                None,
+            ),
+        }
+    }
+
+    fn from_row(action: &str, course_id: Option<u32>) -> Option<AuditAction> {
+        match (action, course_id) {
+            ("subscribe", Some(course_id)) => Some(AuditAction::Subscribe(course_id)),
+            ("unsubscribe", Some(course_id)) => Some(AuditAction::Unsubscribe(course_id)),
+            ("language_default", _) => Some(AuditAction::SetLanguage(None)),
+            (action, _) if action.starts_with("language_") => {
+                Language::from_code(&action["language_".len()..])
+                    .map(|l| AuditAction::SetLanguage(Some(l)))
+            }
+            (action, _) => match action.strip_prefix("template_") {
+                Some(key) => TemplateKind::from_key(key).map(AuditAction::SetTemplate),
+                None => action
+                    .strip_prefix("policy_")
+                    .and_then(GroupPolicy::from_str)
+                    .map(AuditAction::SetPolicy),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditEntry {
+    /// Nickname of the user who made the change
+    pub nickname: String,
+    pub action: AuditAction,
+    /// Cached name of the course changed
+    pub course_name: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+pub async fn record_audit(user_id: u32, tenant: Tenant, action: AuditAction) -> Result<(), Error> {
+    let (tenant_type, tenant_id) = tenant.key(user_id);
+    let (action, course_id) = action.to_row();
+    CONN.lock().await.execute(
+        "INSERT INTO `audit_log` (`user_id`, `tenant_type`, `tenant_id`, `action`, `course_id`, `created_at`) \
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![user_id, tenant_type, tenant_id, action, course_id, Utc::now().naive_utc()],
+    )?;
+    Ok(())
+}
+
+/// Latest changes made to a tenant, latest first. `user_id` identifies
+/// personal tenants.
+pub async fn list_audit(
+    tenant: Tenant,
+    user_id: u32,
+    limit: u32,
+) -> Result<Vec<AuditEntry>, Error> {
+    let (tenant_type, tenant_id) = tenant.key(user_id);
+    let conn = CONN.lock().await;
+    let mut stmt = conn.prepare_cached(
+        "SELECT `u`.`nickname`, `a`.`action`, `a`.`course_id`, `c`.`name`, `a`.`created_at` \
+        FROM `audit_log` AS 'a' \
+        INNER JOIN `user` AS 'u' ON `u`.`id` = `a`.`user_id` \
+        LEFT JOIN `course` AS 'c' ON `c`.`course_id` = `a`.`course_id` \
+        WHERE `a`.`tenant_type` = ?1 AND `a`.`tenant_id` = ?2 \
+        ORDER BY `a`.`id` DESC LIMIT ?3",
+    )?;
+    let rows = stmt
+        .query_map(params![tenant_type, tenant_id, limit], |row| {
+            let action: String = row.get(1)?;
+            Ok((
+                row.get(0)?,
+                AuditAction::from_row(action.as_str(), row.get(2)?),
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(rows
+        .into_iter()
+        .filter_map(|(nickname, action, course_name, created_at)| {
+            Some(AuditEntry {
+                nickname,
+                action: action?,
+                course_name,
+                created_at,
+            })
+        })
+        .collect())
+}
+
+#[test]
+fn audit_action_test() {
+    for action in &[
+        AuditAction::Subscribe(1234),
+        AuditAction::Unsubscribe(1234),
+        AuditAction::SetPolicy(GroupPolicy::Anyone),
+        AuditAction::SetPolicy(GroupPolicy::Admin),
+        AuditAction::SetTemplate(TemplateKind::PublishMany),
+        AuditAction::SetLanguage(Some(Language::English)),
+        AuditAction::SetLanguage(None),
+    ] {
+        let (name, course_id) = action.to_row();
+        assert_eq!(
+            AuditAction::from_row(name.as_str(), course_id),
+            Some(*action)
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use crate::assignment::start_assignment_loop;
+use crate::calendar::start_calendar_loop;
+use crate::check::start_check_loop;
+use crate::config::CONFIG;
+use crate::forum::start_forum_loop;
+use crate::handler::{handle_group_message, handle_leave_group, handle_private_message};
+use crate::messenger::{set_messenger, start_digest_loop, start_telegram_loop, CoolQMessenger};
+use crate::migrate;
+use crate::permission::Role;
+use coolq_sdk_rust::api::{get_login_qq, set_friend_add_request, set_group_add_request_v2};
+use coolq_sdk_rust::events::{
+    AddFriendRequestEvent, AddGroupRequestEvent, GroupMemberDecreaseEvent, GroupMessageEvent,
+    PrivateMessageEvent,
+};
+use coolq_sdk_rust::prelude::listener;
+use coolq_sdk_rust::targets::cqcode::CQCode;
+use coolq_sdk_rust::targets::group::Group;
+use coolq_sdk_rust::targets::user::Authority;
+use lazy_static::lazy_static;
+use std::sync::Arc;
+use zeroize::Zeroize;
+
+lazy_static! {
+    pub static ref MY_QQ: i64 = get_login_qq().expect("Cannot parse my QQ").into();
+}
+
+#[coolq_sdk_rust::main]
+fn main() {
+    set_messenger(Arc::new(CoolQMessenger));
+    migrate();
+    coolq_sdk_rust::ASYNC_RUNTIME.spawn(start_check_loop());
+    coolq_sdk_rust::ASYNC_RUNTIME.spawn(start_assignment_loop());
+    coolq_sdk_rust::ASYNC_RUNTIME.spawn(start_forum_loop());
+    coolq_sdk_rust::ASYNC_RUNTIME.spawn(start_calendar_loop());
+    if CONFIG.telegram.token.is_some() {
+        coolq_sdk_rust::ASYNC_RUNTIME.spawn(start_telegram_loop());
+    }
+    if CONFIG.email.host.is_some() {
+        coolq_sdk_rust::ASYNC_RUNTIME.spawn(start_digest_loop());
+    }
+}
+
+#[listener]
+async fn on_private_message(mut event: PrivateMessageEvent) {
+    handle_private_message(
+        event.user.user_id,
+        event.user.nickname.as_str(),
+        event.msg.msg.as_str(),
+    )
+    .await;
+    // The message may contain a password
+    event.msg.msg.zeroize();
+    event.msg.raw_msg.zeroize();
+}
+
+#[listener(priority = "low")]
+async fn on_group_message(event: GroupMessageEvent) {
+    let atme = event.msg.cqcodes.iter().any(|c| match c {
+        CQCode::At(qq) => MY_QQ.eq(qq),
+        _ => false,
+    });
+    if !atme {
+        return;
+    }
+    let role = match event.user.authority {
+        Authority::Master | Authority::SuperAdmin | Authority::GroupOwner => Role::Owner,
+        Authority::GroupAdmin => Role::Admin,
+        Authority::User => Role::Member,
+    };
+    handle_group_message(
+        event.group.group_id,
+        event.user.user_id,
+        role,
+        event.msg.msg.as_str(),
+    )
+    .await;
+}
+
+#[listener]
+async fn group_member_decrease(event: GroupMemberDecreaseEvent) {
+    if MY_QQ.eq(&event.being_operate_user.user_id) {
+        // 被踢出群
+        let Group {
+            group_id,
+            group_name,
+            ..
+        } = event.group;
+        handle_leave_group(group_id, group_name.as_str()).await;
+    }
+}
+
+#[listener]
+fn add_friend_request(event: AddFriendRequestEvent) {
+    set_friend_add_request(event.flag, true, "").expect("添加好友请求处理失败");
+}
+
+#[listener]
+fn add_group_request(event: AddGroupRequestEvent) {
+    if event.sub_type == 2 {
+        set_group_add_request_v2(event.flag, event.sub_type, true, "").expect("无法受邀加入群");
+    }
+}
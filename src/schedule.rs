@@ -0,0 +1,160 @@
+use crate::tenant::Tenant;
+use chrono::{DateTime, Duration, Local, Timelike};
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Polling intervals, all in seconds.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Schedule {
+    /// Default interval between two checks of a subscription
+    pub interval: u64,
+    /// Upper bound of the random delay added to every interval
+    pub jitter: u64,
+    pub night: Option<NightSchedule>,
+    /// Overrides keyed by course ID
+    pub courses: HashMap<u32, u64>,
+    /// Overrides keyed by group QQ
+    pub groups: HashMap<i64, u64>,
+    /// Overrides of personal subscriptions keyed by user QQ
+    pub users: HashMap<i64, u64>,
+    /// Overrides keyed by Telegram chat ID
+    pub telegram_chats: HashMap<i64, u64>,
+    /// Overrides keyed by webhook ID
+    pub webhooks: HashMap<i64, u64>,
+    /// Consecutive failures after which a subscription is paused
+    pub failure_threshold: u32,
+    /// Delay before the first retry of a paused subscription, doubled after
+    /// each failed retry
+    pub retry_interval: u64,
+    /// Upper bound of the retry delay
+    pub max_retry_interval: u64,
+}
+
+/// Slower polling between `start` and `end` o'clock, local time.
+#[derive(Debug, Deserialize)]
+pub struct NightSchedule {
+    pub start: u32,
+    pub end: u32,
+    pub interval: u64,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule {
+            interval: 60 * 5,
+            jitter: 30,
+            night: Some(NightSchedule {
+                start: 1,
+                end: 7,
+                interval: 60 * 30,
+            }),
+            courses: HashMap::new(),
+            groups: HashMap::new(),
+            users: HashMap::new(),
+            telegram_chats: HashMap::new(),
+            webhooks: HashMap::new(),
+            failure_threshold: 3,
+            retry_interval: 60 * 60,
+            max_retry_interval: 60 * 60 * 24,
+        }
+    }
+}
+
+impl NightSchedule {
+    fn contains(&self, hour: u32) -> bool {
+        if self.start <= self.end {
+            self.start <= hour && hour < self.end
+        } else {
+            // Wraps around midnight
+            self.start <= hour || hour < self.end
+        }
+    }
+}
+
+impl Schedule {
+    /// Interval of the given subscription at `hour` o'clock, local time.
+    pub fn interval_of(&self, tenant: Tenant, user_qq: i64, course_id: u32, hour: u32) -> u64 {
+        let interval = self
+            .courses
+            .get(&course_id)
+            .or_else(|| match tenant {
+                Tenant::Group(group_qq) => self.groups.get(&group_qq),
+                Tenant::SenderSelf | Tenant::Email(_) => self.users.get(&user_qq),
+                Tenant::Telegram(chat_id) => self.telegram_chats.get(&chat_id),
+                Tenant::Webhook(webhook_id) => self.webhooks.get(&webhook_id),
+            })
+            .copied()
+            .unwrap_or(self.interval);
+        match &self.night {
+            Some(night) if night.contains(hour) => interval.max(night.interval),
+            _ => interval,
+        }
+    }
+
+    pub fn is_paused(&self, failure_count: u32) -> bool {
+        failure_count >= self.failure_threshold
+    }
+
+    /// Delay before retrying a subscription paused after `failure_count`
+    /// consecutive failures.
+    pub fn retry_delay(&self, failure_count: u32) -> u64 {
+        let retries = failure_count.saturating_sub(self.failure_threshold).min(32);
+        self.retry_interval
+            .saturating_mul(1 << retries)
+            .min(self.max_retry_interval)
+    }
+
+    /// When the given subscription should be checked next, jitter included.
+    pub fn next_check(
+        &self,
+        tenant: Tenant,
+        user_qq: i64,
+        course_id: u32,
+        now: DateTime<Local>,
+    ) -> DateTime<Local> {
+        let jitter = if self.jitter > 0 {
+            rand::thread_rng().gen_range(0, self.jitter)
+        } else {
+            0
+        };
+        now + Duration::seconds(
+            (self.interval_of(tenant, user_qq, course_id, now.hour()) + jitter) as i64,
+        )
+    }
+}
+
+#[test]
+fn interval_of_test() {
+    let schedule: Schedule = serde_json::from_str(
+        r#"{
+            "interval": 300,
+            "night": { "start": 23, "end": 6, "interval": 1800 },
+            "courses": { "42": 60 },
+            "groups": { "1001": 600 }
+        }"#,
+    )
+    .unwrap();
+    let (day, night) = (12, 23);
+    assert_eq!(schedule.interval_of(Tenant::Group(1), 0, 1, day), 300);
+    assert_eq!(schedule.interval_of(Tenant::Group(1001), 0, 1, day), 600);
+    assert_eq!(schedule.interval_of(Tenant::Group(1001), 0, 42, day), 60);
+    assert_eq!(schedule.interval_of(Tenant::SenderSelf, 1001, 1, day), 300);
+    assert_eq!(
+        schedule.interval_of(Tenant::Group(1001), 0, 42, night),
+        1800
+    );
+}
+
+#[test]
+fn retry_delay_test() {
+    let schedule = Schedule::default();
+    assert!(!schedule.is_paused(2));
+    assert!(schedule.is_paused(3));
+    assert_eq!(schedule.retry_delay(3), 3600);
+    assert_eq!(schedule.retry_delay(4), 7200);
+    assert_eq!(schedule.retry_delay(7), 57600);
+    assert_eq!(schedule.retry_delay(8), 86400);
+    assert_eq!(schedule.retry_delay(100), 86400);
+}
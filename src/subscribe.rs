@@ -1,10 +1,14 @@
+use crate::config::CONFIG;
 use crate::error::Error;
 use crate::moodle::{get_course_content, CourseSection};
 use crate::tenant::Tenant;
-use crate::user::get_user_moodle_token;
+use crate::user::{
+    get_user_credential, get_user_moodle_token, login_moodle, save_user_token, set_token_expired,
+};
 use crate::CONN;
-use chrono::Utc;
-use rusqlite::{params, Error as DbError, OptionalExtension, Row, Transaction};
+use chrono::{NaiveDateTime, Utc};
+use rusqlite::{params, Connection, Error as DbError, OptionalExtension, Row, Transaction};
+use std::collections::HashMap;
 
 fn save_course_modules(
     tx: &Transaction,
@@ -14,26 +18,39 @@ fn save_course_modules(
 ) -> Result<(), Error> {
     let mut stmt = tx.prepare(
         "INSERT INTO `user_course_module`\
-        (`user_id`, `course_id`, `module_id`, `updated_at`)\
-        VALUES (?1, ?2, ?3, ?4)",
+        (`user_id`, `course_id`, `module_id`, `updated_at`, `last_modified`, `module_name`, \
+        `visible`, `content_hash`) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
     )?;
     // Use current UTC time instead of lastupdate from module info
     // to avoid inconsistency
     let updated_at = Utc::now().naive_utc();
     for section in course_content {
         for module in &section.modules {
-            stmt.execute(params![user_id, course_id, module.id, updated_at])?;
+            stmt.execute(params![
+                user_id,
+                course_id,
+                module.id,
+                updated_at,
+                module.content.last_modified(),
+                module.name,
+                module.user_visible,
+                module.content.content_hash()
+            ])?;
         }
     }
     Ok(())
 }
 
-pub async fn add_subscribe(user_id: u32, course_id: u32, tenant: Tenant) -> Result<(), Error> {
-    // TODO: renew moodle token
-    let mut conn = CONN.lock().await;
-
+/// Find the subscription of `course_id` in `tenant`, subscribed by `user_id`
+/// unless in a chat.
+fn find_subscription(
+    conn: &Connection,
+    user_id: u32,
+    course_id: u32,
+    tenant: Tenant,
+) -> Result<Option<u32>, DbError> {
     static ROW_MATCHER: fn(&Row) -> Result<u32, DbError> = |row: &Row| Ok(row.get(0)?);
-    let existing = match tenant {
+    match tenant {
         Tenant::SenderSelf => conn.query_row(
             "SELECT `id` FROM `user_course_self`\
             WHERE `user_id` = ?1 AND `course_id` = ?2 LIMIT 1",
@@ -46,17 +63,63 @@ pub async fn add_subscribe(user_id: u32, course_id: u32, tenant: Tenant) -> Resu
             params![group_qq, course_id],
             ROW_MATCHER,
         ),
+        Tenant::Telegram(chat_id) => conn.query_row(
+            "SELECT `id` FROM `user_course_telegram`\
+            WHERE `chat_id` = ?1 AND `course_id` = ?2 LIMIT 1",
+            params![chat_id, course_id],
+            ROW_MATCHER,
+        ),
+        Tenant::Webhook(webhook_id) => conn.query_row(
+            "SELECT `id` FROM `user_course_webhook`\
+            WHERE `webhook_id` = ?1 AND `course_id` = ?2 LIMIT 1",
+            params![webhook_id, course_id],
+            ROW_MATCHER,
+        ),
+        Tenant::Email(user_id) => conn.query_row(
+            "SELECT `id` FROM `user_course_email`\
+            WHERE `user_id` = ?1 AND `course_id` = ?2 LIMIT 1",
+            params![user_id, course_id],
+            ROW_MATCHER,
+        ),
     }
-    .optional()?;
-    if let Some(_) = existing {
-        return Err(Error::Duplicated);
-    }
-    // Still holding the lock to avoid races
-    // Check user token
-    let token = get_user_moodle_token(&conn, user_id)?;
+    .optional()
+}
+
+pub async fn add_subscribe(user_id: u32, course_id: u32, tenant: Tenant) -> Result<(), Error> {
+    // The lock is released during requests to Moodle
+    let (token, credential) = {
+        let conn = CONN.lock().await;
+        if find_subscription(&conn, user_id, course_id, tenant)?.is_some() {
+            return Err(Error::Duplicated);
+        }
+        (
+            get_user_moodle_token(&conn, user_id)?,
+            get_user_credential(&conn, user_id)?,
+        )
+    };
     // Check if user can get course content
-    let course_content = get_course_content(token, course_id).await?;
+    let course_content = match get_course_content(token.as_str(), course_id).await {
+        Err(e) if e.is_invalid_token() => {
+            // Renew moodle token if the user allows
+            let (campus_id, password) = match credential {
+                Some(c) => c,
+                None => {
+                    set_token_expired(&*CONN.lock().await, user_id, token.as_str())?;
+                    return Err(e.into());
+                }
+            };
+            let token = login_moodle(campus_id, password).await?;
+            save_user_token(&*CONN.lock().await, user_id, token.as_str())?;
+            get_course_content(token, course_id).await?
+        }
+        r => r?,
+    };
 
+    let mut conn = CONN.lock().await;
+    // Subscribed by another message in the meantime
+    if find_subscription(&conn, user_id, course_id, tenant)?.is_some() {
+        return Err(Error::Duplicated);
+    }
     let tx = conn.transaction()?;
     save_course_modules(&tx, user_id, course_id, &course_content)?;
 
@@ -69,10 +132,22 @@ pub async fn add_subscribe(user_id: u32, course_id: u32, tenant: Tenant) -> Resu
         Tenant::Group(group_qq) => tx.execute(
             "INSERT INTO `user_course_group` (`user_id`, `course_id`, `group_qq`, `created_at`) VALUES (?1, ?2, ?3, ?4)",
             params![user_id, course_id, group_qq, Utc::now().naive_utc()]
+        ),
+        Tenant::Telegram(chat_id) => tx.execute(
+            "INSERT INTO `user_course_telegram` (`user_id`, `course_id`, `chat_id`, `created_at`) VALUES (?1, ?2, ?3, ?4)",
+            params![user_id, course_id, chat_id, Utc::now().naive_utc()]
+        ),
+        Tenant::Webhook(webhook_id) => tx.execute(
+            "INSERT INTO `user_course_webhook` (`user_id`, `course_id`, `webhook_id`, `created_at`) VALUES (?1, ?2, ?3, ?4)",
+            params![user_id, course_id, webhook_id, Utc::now().naive_utc()]
+        ),
+        Tenant::Email(_) => tx.execute(
+            "INSERT INTO `user_course_email` (`user_id`, `course_id`, `created_at`) VALUES (?1, ?2, ?3)",
+            params![user_id, course_id, Utc::now().naive_utc()]
         )
     }?;
     if affected == 0 {
-        return Err(Error::Other("无法添加记录".to_string()));
+        return Err(Error::Localized("error.add_failed", vec![]));
     }
     tx.commit()?;
     Ok(())
@@ -89,6 +164,18 @@ pub async fn remove_subscribe(user_id: u32, course_id: u32, tenant: Tenant) -> R
         Tenant::Group(group_qq) => conn.execute(
             "DELETE FROM `user_course_group` WHERE `user_id` = ?1 AND `course_id` = ?2 AND `group_qq` = ?3",
             params![user_id, course_id, group_qq]
+        ),
+        Tenant::Telegram(chat_id) => conn.execute(
+            "DELETE FROM `user_course_telegram` WHERE `user_id` = ?1 AND `course_id` = ?2 AND `chat_id` = ?3",
+            params![user_id, course_id, chat_id]
+        ),
+        Tenant::Webhook(webhook_id) => conn.execute(
+            "DELETE FROM `user_course_webhook` WHERE `user_id` = ?1 AND `course_id` = ?2 AND `webhook_id` = ?3",
+            params![user_id, course_id, webhook_id]
+        ),
+        Tenant::Email(_) => conn.execute(
+            "DELETE FROM `user_course_email` WHERE `user_id` = ?1 AND `course_id` = ?2",
+            params![user_id, course_id]
         )
     }?;
 
@@ -98,6 +185,27 @@ pub async fn remove_subscribe(user_id: u32, course_id: u32, tenant: Tenant) -> R
     }
 }
 
+/// Remove the subscription to a course of a group or Telegram chat, whoever
+/// subscribed it.
+pub async fn remove_chat_subscribe(course_id: u32, tenant: Tenant) -> Result<(), Error> {
+    let conn = CONN.lock().await;
+    let affected = match tenant {
+        Tenant::Group(group_qq) => conn.execute(
+            "DELETE FROM `user_course_group` WHERE `course_id` = ?1 AND `group_qq` = ?2",
+            params![course_id, group_qq],
+        )?,
+        Tenant::Telegram(chat_id) => conn.execute(
+            "DELETE FROM `user_course_telegram` WHERE `course_id` = ?1 AND `chat_id` = ?2",
+            params![course_id, chat_id],
+        )?,
+        _ => 0,
+    };
+    match affected {
+        0 => Err(Error::NotExist),
+        _ => Ok(()),
+    }
+}
+
 pub async fn remove_group_subscribe(group_qq: i64) -> Result<usize, Error> {
     Ok(CONN.lock().await.execute(
         "DELETE FROM `user_course_group` WHERE `group_qq` = ?1",
@@ -105,6 +213,209 @@ pub async fn remove_group_subscribe(group_qq: i64) -> Result<usize, Error> {
     )?)
 }
 
+pub async fn remove_telegram_subscribe(chat_id: i64) -> Result<usize, Error> {
+    Ok(CONN.lock().await.execute(
+        "DELETE FROM `user_course_telegram` WHERE `chat_id` = ?1",
+        params![chat_id],
+    )?)
+}
+
+/// Tenants with active subscriptions to a course, each with the QQ of the
+/// receiver for personal subscriptions.
+pub async fn get_course_tenants(course_id: u32) -> Result<Vec<(Tenant, i64)>, Error> {
+    let threshold = CONFIG.schedule.failure_threshold;
+    let conn = CONN.lock().await;
+    let mut group_stmt = conn.prepare_cached(
+        "SELECT DISTINCT `group_qq` FROM `user_course_group`\
+        WHERE `course_id` = ?1 AND `failure_count` < ?2",
+    )?;
+    let mut self_stmt = conn.prepare_cached(
+        "SELECT `u`.`qq` FROM `user_course_self` AS 's'\
+        INNER JOIN `user` AS 'u' ON `u`.`id` = `s`.`user_id`\
+        WHERE `s`.`course_id` = ?1 AND `s`.`failure_count` < ?2",
+    )?;
+    let mut telegram_stmt = conn.prepare_cached(
+        "SELECT DISTINCT `chat_id` FROM `user_course_telegram`\
+        WHERE `course_id` = ?1 AND `failure_count` < ?2",
+    )?;
+    let mut webhook_stmt = conn.prepare_cached(
+        "SELECT DISTINCT `webhook_id` FROM `user_course_webhook`\
+        WHERE `course_id` = ?1 AND `failure_count` < ?2",
+    )?;
+    let mut email_stmt = conn.prepare_cached(
+        "SELECT `user_id` FROM `user_course_email`\
+        WHERE `course_id` = ?1 AND `failure_count` < ?2",
+    )?;
+    let tenants = group_stmt
+        .query_map(params![course_id, threshold], |row| {
+            Ok((Tenant::Group(row.get(0)?), 0))
+        })?
+        .chain(self_stmt.query_map(params![course_id, threshold], |row| {
+            Ok((Tenant::SenderSelf, row.get(0)?))
+        })?)
+        .chain(
+            telegram_stmt.query_map(params![course_id, threshold], |row| {
+                Ok((Tenant::Telegram(row.get(0)?), 0))
+            })?,
+        )
+        .chain(
+            webhook_stmt.query_map(params![course_id, threshold], |row| {
+                Ok((Tenant::Webhook(row.get(0)?), 0))
+            })?,
+        )
+        .chain(email_stmt.query_map(params![course_id, threshold], |row| {
+            Ok((Tenant::Email(row.get(0)?), 0))
+        })?)
+        .collect::<Result<_, _>>()?;
+    Ok(tenants)
+}
+
+/// Courses subscribed by a tenant. `user_id` identifies the subscriber of
+/// personal subscriptions.
+pub async fn get_tenant_courses(tenant: Tenant, user_id: u32) -> Result<Vec<u32>, Error> {
+    let conn = CONN.lock().await;
+    let course_ids = match tenant {
+        Tenant::SenderSelf => conn
+            .prepare_cached("SELECT `course_id` FROM `user_course_self` WHERE `user_id` = ?1")?
+            .query_map(params![user_id], |row| row.get(0))?
+            .collect::<Result<_, _>>(),
+        Tenant::Group(group_qq) => conn
+            .prepare_cached(
+                "SELECT DISTINCT `course_id` FROM `user_course_group` WHERE `group_qq` = ?1",
+            )?
+            .query_map(params![group_qq], |row| row.get(0))?
+            .collect::<Result<_, _>>(),
+        Tenant::Telegram(chat_id) => conn
+            .prepare_cached(
+                "SELECT DISTINCT `course_id` FROM `user_course_telegram` WHERE `chat_id` = ?1",
+            )?
+            .query_map(params![chat_id], |row| row.get(0))?
+            .collect::<Result<_, _>>(),
+        Tenant::Webhook(webhook_id) => conn
+            .prepare_cached(
+                "SELECT DISTINCT `course_id` FROM `user_course_webhook` WHERE `webhook_id` = ?1",
+            )?
+            .query_map(params![webhook_id], |row| row.get(0))?
+            .collect::<Result<_, _>>(),
+        Tenant::Email(user_id) => conn
+            .prepare_cached("SELECT `course_id` FROM `user_course_email` WHERE `user_id` = ?1")?
+            .query_map(params![user_id], |row| row.get(0))?
+            .collect::<Result<_, _>>(),
+    }?;
+    Ok(course_ids)
+}
+
+/// A subscription listed by the "列表" command
+#[derive(Debug)]
+pub struct SubscriptionInfo {
+    pub course_id: u32,
+    /// Cached name of the course
+    pub course_name: Option<String>,
+    /// Nickname of the subscriber
+    pub subscriber: String,
+    pub created_at: NaiveDateTime,
+    pub failure_count: u32,
+    /// Whether the Moodle login of the subscriber has expired
+    pub token_expired: bool,
+}
+
+/// Table of the subscriptions of a tenant, with the column and the value
+/// identifying the tenant in it. `user_id` identifies the subscriber of
+/// personal subscriptions.
+fn tenant_table(tenant: Tenant, user_id: u32) -> (&'static str, &'static str, i64) {
+    match tenant {
+        Tenant::SenderSelf => ("user_course_self", "user_id", user_id as i64),
+        Tenant::Group(group_qq) => ("user_course_group", "group_qq", group_qq),
+        Tenant::Telegram(chat_id) => ("user_course_telegram", "chat_id", chat_id),
+        Tenant::Webhook(webhook_id) => ("user_course_webhook", "webhook_id", webhook_id),
+        Tenant::Email(user_id) => ("user_course_email", "user_id", user_id as i64),
+    }
+}
+
+/// Subscriptions of a tenant, oldest first. `user_id` identifies the
+/// subscriber of personal subscriptions.
+pub async fn list_tenant_subscriptions(
+    tenant: Tenant,
+    user_id: u32,
+) -> Result<Vec<SubscriptionInfo>, Error> {
+    let (table, column, key) = tenant_table(tenant, user_id);
+    let conn = CONN.lock().await;
+    let mut stmt = conn.prepare_cached(
+        format!(
+            "SELECT `s`.`course_id`, `c`.`name`, `u`.`nickname`, `s`.`created_at`, \
+            `s`.`failure_count`, `u`.`token_expired` FROM `{}` AS 's' \
+            INNER JOIN `user` AS 'u' ON `u`.`id` = `s`.`user_id` \
+            LEFT JOIN `course` AS 'c' ON `c`.`course_id` = `s`.`course_id` \
+            WHERE `s`.`{}` = ?1 ORDER BY `s`.`created_at`",
+            table, column
+        )
+        .as_str(),
+    )?;
+    let subscriptions = stmt
+        .query_map(params![key], |row| {
+            Ok(SubscriptionInfo {
+                course_id: row.get(0)?,
+                course_name: row.get(1)?,
+                subscriber: row.get(2)?,
+                created_at: row.get(3)?,
+                failure_count: row.get(4)?,
+                token_expired: row.get(5)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(subscriptions)
+}
+
+/// Reset the failure count of the subscriptions of a tenant to a course, or
+/// to all courses, so that paused ones are checked again. Returns the number
+/// of subscriptions that had failed.
+pub async fn reset_failures(
+    tenant: Tenant,
+    user_id: u32,
+    course_id: Option<u32>,
+) -> Result<usize, Error> {
+    let (table, column, key) = tenant_table(tenant, user_id);
+    let affected = CONN.lock().await.execute(
+        format!(
+            "UPDATE `{}` SET `failure_count` = 0 \
+            WHERE `{}` = ?1 AND IFNULL(`course_id` = ?2, 1) AND `failure_count` > 0",
+            table, column
+        )
+        .as_str(),
+        params![key, course_id],
+    )?;
+    Ok(affected)
+}
+
+/// Courses with active subscriptions, grouped by the Moodle token of one of
+/// their subscribers. One token is enough for each course.
+pub async fn get_token_courses() -> Result<HashMap<String, Vec<u32>>, Error> {
+    let course_tokens: HashMap<u32, String> = {
+        let conn = CONN.lock().await;
+        let mut stmt = conn.prepare_cached(
+            "SELECT `s`.`course_id`, `u`.`moodle_token` FROM (\
+                SELECT `user_id`, `course_id` FROM `user_course_group` WHERE `failure_count` < ?1 \
+                UNION SELECT `user_id`, `course_id` FROM `user_course_self` WHERE `failure_count` < ?1 \
+                UNION SELECT `user_id`, `course_id` FROM `user_course_telegram` WHERE `failure_count` < ?1 \
+                UNION SELECT `user_id`, `course_id` FROM `user_course_webhook` WHERE `failure_count` < ?1 \
+                UNION SELECT `user_id`, `course_id` FROM `user_course_email` WHERE `failure_count` < ?1\
+            ) AS 's' INNER JOIN `user` AS 'u' ON `u`.`id` = `s`.`user_id`\
+            WHERE `u`.`token_expired` = 0 AND `u`.`banned` = 0",
+        )?;
+        let rows = stmt
+            .query_map(params![CONFIG.schedule.failure_threshold], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect::<Result<_, _>>()?;
+        rows
+    };
+    let mut token_courses: HashMap<String, Vec<u32>> = HashMap::new();
+    for (course_id, token) in course_tokens {
+        token_courses.entry(token).or_default().push(course_id);
+    }
+    Ok(token_courses)
+}
+
 #[tokio::test]
 async fn test_add_remove_self_subscribe() -> Result<(), Error> {
     let conn = CONN.lock().await;
@@ -113,9 +424,10 @@ async fn test_add_remove_self_subscribe() -> Result<(), Error> {
     drop(conn);
 
     let tenant = Tenant::SenderSelf;
-    let user_id = crate::user::get_user_id_from_qq(env!("CQMS_QQ").parse().unwrap())
-        .await
-        .unwrap();
+    let user_id =
+        crate::user::get_user_id(crate::user::Account::Qq(env!("CQMS_QQ").parse().unwrap()))
+            .await
+            .unwrap();
     let course_id = env!("CQMS_COURSE_ID").parse::<u32>().unwrap();
     add_subscribe(user_id, course_id, tenant).await?;
     println!("Added course to self");
@@ -149,9 +461,10 @@ async fn test_add_remove_group_subscribe() -> Result<(), Error> {
     conn.execute("DELETE FROM `user_course_group`", params![])?;
     drop(conn);
 
-    let user_id = crate::user::get_user_id_from_qq(env!("CQMS_QQ").parse().unwrap())
-        .await
-        .unwrap();
+    let user_id =
+        crate::user::get_user_id(crate::user::Account::Qq(env!("CQMS_QQ").parse().unwrap()))
+            .await
+            .unwrap();
     let tenant = Tenant::Group(env!("CQMS_QQ_GROUP").parse().unwrap());
     let course_id = env!("CQMS_COURSE_ID").parse::<u32>().unwrap();
     add_subscribe(user_id, course_id, tenant).await?;
@@ -181,9 +494,10 @@ async fn test_add_remove_group_subscribe() -> Result<(), Error> {
 
 #[tokio::test]
 async fn test_remove_group_subscription() -> Result<(), Error> {
-    let user_id = crate::user::get_user_id_from_qq(env!("CQMS_QQ").parse().unwrap())
-        .await
-        .unwrap();
+    let user_id =
+        crate::user::get_user_id(crate::user::Account::Qq(env!("CQMS_QQ").parse().unwrap()))
+            .await
+            .unwrap();
     let group_qq = env!("CQMS_QQ_GROUP").parse().unwrap();
     let tenant = Tenant::Group(group_qq);
     let course_id = env!("CQMS_COURSE_ID").parse::<u32>().unwrap();
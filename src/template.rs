@@ -0,0 +1,434 @@
+use crate::error::Error;
+use crate::locale::{tr, trf, Language, LANGUAGES};
+use crate::messenger::{log, LogLevel};
+use crate::tenant::Tenant;
+use crate::CONN;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use rusqlite::params;
+use std::collections::HashMap;
+use std::mem::{replace, take};
+
+/// Variables available in every template, described by
+/// `template.variable.<name>` messages
+pub static VARIABLES: &[&str] = &[
+    "course_name",
+    "module_name",
+    "module_type",
+    "visible",
+    "url",
+    "count",
+];
+
+/// Kinds of notifications sent on course updates. Single changes have all
+/// variables, the "many" ones only `course_name` and `count`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TemplateKind {
+    Publish,
+    Update,
+    PublishMany,
+    UpdateMany,
+    Show,
+    ShowMany,
+    Hide,
+    HideMany,
+    Remove,
+    RemoveMany,
+}
+
+pub static TEMPLATE_KINDS: &[TemplateKind] = &[
+    TemplateKind::Publish,
+    TemplateKind::Update,
+    TemplateKind::PublishMany,
+    TemplateKind::UpdateMany,
+    TemplateKind::Show,
+    TemplateKind::ShowMany,
+    TemplateKind::Hide,
+    TemplateKind::HideMany,
+    TemplateKind::Remove,
+    TemplateKind::RemoveMany,
+];
+
+impl TemplateKind {
+    /// Key stored in the database
+    pub fn key(self) -> &'static str {
+        match self {
+            TemplateKind::Publish => "publish",
+            TemplateKind::Update => "update",
+            TemplateKind::PublishMany => "publish_many",
+            TemplateKind::UpdateMany => "update_many",
+            TemplateKind::Show => "show",
+            TemplateKind::ShowMany => "show_many",
+            TemplateKind::Hide => "hide",
+            TemplateKind::HideMany => "hide_many",
+            TemplateKind::Remove => "remove",
+            TemplateKind::RemoveMany => "remove_many",
+        }
+    }
+
+    /// Name shown in `language`. The Chinese names are accepted as well as
+    /// the keys.
+    pub fn name(self, language: Language) -> &'static str {
+        if language == Language::English {
+            return self.key();
+        }
+        match self {
+            TemplateKind::Publish => "发布",
+            TemplateKind::Update => "更新",
+            TemplateKind::PublishMany => "批量发布",
+            TemplateKind::UpdateMany => "批量更新",
+            TemplateKind::Show => "公开",
+            TemplateKind::ShowMany => "批量公开",
+            TemplateKind::Hide => "隐藏",
+            TemplateKind::HideMany => "批量隐藏",
+            TemplateKind::Remove => "移除",
+            TemplateKind::RemoveMany => "批量移除",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<TemplateKind> {
+        TEMPLATE_KINDS.iter().copied().find(|k| k.key() == key)
+    }
+
+    /// Look up a kind by its key or name.
+    pub fn find(word: &str) -> Option<TemplateKind> {
+        TEMPLATE_KINDS
+            .iter()
+            .copied()
+            .find(|k| k.key() == word || k.name(Language::Chinese) == word)
+    }
+
+    fn default_source(self, language: Language) -> &'static str {
+        if language == Language::English {
+            return match self {
+                TemplateKind::Publish => {
+                    "{{course_name}} published a new {{#if visible}}{{else}}hidden {{/if}}\
+                    {{module_type}}: {{module_name}}"
+                }
+                TemplateKind::Update => {
+                    "{{course_name}} updated the {{#if visible}}{{else}}hidden {{/if}}\
+                    {{module_type}} {{module_name}}"
+                }
+                TemplateKind::PublishMany => "{{course_name}} published {{count}} items",
+                TemplateKind::UpdateMany => "{{course_name}} updated {{count}} items",
+                TemplateKind::Show => {
+                    "{{course_name}}: the {{module_type}} {{module_name}} is visible now"
+                }
+                TemplateKind::ShowMany => "{{course_name}}: {{count}} items are visible now",
+                TemplateKind::Hide => {
+                    "{{course_name}}: the {{module_type}} {{module_name}} is hidden again"
+                }
+                TemplateKind::HideMany => "{{course_name}} hid {{count}} items",
+                TemplateKind::Remove => "{{course_name}} removed {{module_name}}",
+                TemplateKind::RemoveMany => "{{course_name}} removed {{count}} items",
+            };
+        }
+        match self {
+            TemplateKind::Publish => {
+                "{{course_name}} 发布了一个{{#if visible}}{{else}}隐藏的{{/if}}{{module_type}} \
+                {{module_name}}，快去看看吧"
+            }
+            TemplateKind::Update => {
+                "{{course_name}} 更新了一个{{#if visible}}{{else}}隐藏的{{/if}}{{module_type}} \
+                {{module_name}}，快去看看吧"
+            }
+            TemplateKind::PublishMany => "{{course_name}} 发布了 {{count}} 个内容，快去看看吧",
+            TemplateKind::UpdateMany => "{{course_name}} 更新了 {{count}} 个内容，快去看看吧",
+            TemplateKind::Show => {
+                "{{course_name}} 的{{module_type}} {{module_name}} 现在可见了，快去看看吧"
+            }
+            TemplateKind::ShowMany => "{{course_name}} 有 {{count}} 个内容现在可见了，快去看看吧",
+            TemplateKind::Hide => "{{course_name}} 的{{module_type}} {{module_name}} 又被隐藏了",
+            TemplateKind::HideMany => "{{course_name}} 隐藏了 {{count}} 个内容",
+            TemplateKind::Remove => "{{course_name}} 移除了 {{module_name}}",
+            TemplateKind::RemoveMany => "{{course_name}} 移除了 {{count}} 个内容",
+        }
+    }
+}
+
+/// Why a template cannot be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateError {
+    UnclosedTag,
+    UnknownVariable(String),
+    UnexpectedElse,
+    UnexpectedEndIf,
+    UnclosedIf,
+}
+
+impl TemplateError {
+    pub fn localize(&self, language: Language) -> String {
+        match self {
+            TemplateError::UnclosedTag => tr(language, "template.error.unclosed_tag").to_string(),
+            TemplateError::UnknownVariable(name) => {
+                trf(language, "template.error.unknown_variable", &[name])
+            }
+            TemplateError::UnexpectedElse => tr(language, "template.error.else").to_string(),
+            TemplateError::UnexpectedEndIf => tr(language, "template.error.end_if").to_string(),
+            TemplateError::UnclosedIf => tr(language, "template.error.unclosed_if").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Var(String),
+    If {
+        var: String,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A template in a Handlebars-like syntax: `{{name}}` is replaced by the
+/// variable, and `{{#if name}}…{{else}}…{{/if}}` is rendered depending on
+/// whether the variable is empty, "0" or "false".
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    source: String,
+    nodes: Vec<Node>,
+}
+
+fn check_variable(name: &str) -> Result<String, TemplateError> {
+    if VARIABLES.contains(&name) {
+        Ok(name.to_string())
+    } else {
+        Err(TemplateError::UnknownVariable(name.to_string()))
+    }
+}
+
+fn is_truthy(value: &str) -> bool {
+    !matches!(value, "" | "0" | "false")
+}
+
+fn render_nodes(nodes: &[Node], vars: &HashMap<&str, String>, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text.as_str()),
+            Node::Var(var) => output.push_str(vars.get(var.as_str()).map_or("", |v| v.as_str())),
+            Node::If {
+                var,
+                then,
+                otherwise,
+            } => {
+                let value = vars.get(var.as_str()).map_or("", |v| v.as_str());
+                if is_truthy(value) {
+                    render_nodes(then.as_slice(), vars, output)
+                } else {
+                    render_nodes(otherwise.as_slice(), vars, output)
+                }
+            }
+        }
+    }
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, TemplateError> {
+        // Enclosing blocks: the variable, the nodes before the block, and
+        // the "then" branch once `{{else}}` is met
+        let mut blocks: Vec<(String, Vec<Node>, Option<Vec<Node>>)> = Vec::new();
+        let mut nodes = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                nodes.push(Node::Text(rest[..start].to_string()));
+            }
+            let end = match rest[start..].find("}}") {
+                Some(end) => start + end,
+                None => return Err(TemplateError::UnclosedTag),
+            };
+            let tag = rest[start + 2..end].trim();
+            rest = &rest[end + 2..];
+            if let Some(var) = tag.strip_prefix("#if ") {
+                let var = check_variable(var.trim())?;
+                blocks.push((var, take(&mut nodes), None));
+            } else if tag == "else" {
+                match blocks.last_mut() {
+                    Some((_, _, then @ None)) => *then = Some(take(&mut nodes)),
+                    _ => return Err(TemplateError::UnexpectedElse),
+                }
+            } else if tag == "/if" {
+                let (var, outer, then) = blocks.pop().ok_or(TemplateError::UnexpectedEndIf)?;
+                let block = replace(&mut nodes, outer);
+                let (then, otherwise) = match then {
+                    Some(then) => (then, block),
+                    None => (block, Vec::new()),
+                };
+                nodes.push(Node::If {
+                    var,
+                    then,
+                    otherwise,
+                });
+            } else {
+                nodes.push(Node::Var(check_variable(tag)?));
+            }
+        }
+        if !rest.is_empty() {
+            nodes.push(Node::Text(rest.to_string()));
+        }
+        if !blocks.is_empty() {
+            return Err(TemplateError::UnclosedIf);
+        }
+        Ok(Template {
+            source: source.to_string(),
+            nodes,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        self.source.as_str()
+    }
+
+    pub fn render(&self, vars: &HashMap<&str, String>) -> String {
+        let mut output = String::new();
+        render_nodes(self.nodes.as_slice(), vars, &mut output);
+        output
+    }
+}
+
+lazy_static! {
+    static ref DEFAULT_TEMPLATES: HashMap<(TemplateKind, Language), Template> = TEMPLATE_KINDS
+        .iter()
+        .flat_map(|&kind| LANGUAGES.iter().map(move |&language| (kind, language)))
+        .map(|(kind, language)| {
+            let template = Template::parse(kind.default_source(language))
+                .expect("Cannot parse default template");
+            ((kind, language), template)
+        })
+        .collect();
+}
+
+/// Templates of a tenant, falling back to the default ones in `language`.
+#[derive(Debug, Default)]
+pub struct Templates {
+    language: Language,
+    overrides: HashMap<TemplateKind, Template>,
+}
+
+impl Templates {
+    pub fn new(language: Language) -> Self {
+        Templates {
+            language,
+            overrides: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, kind: TemplateKind) -> &Template {
+        self.overrides
+            .get(&kind)
+            .unwrap_or_else(|| &DEFAULT_TEMPLATES[&(kind, self.language)])
+    }
+
+    /// Language of the default templates
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    pub fn is_custom(&self, kind: TemplateKind) -> bool {
+        self.overrides.contains_key(&kind)
+    }
+
+    pub fn render(&self, kind: TemplateKind, vars: &HashMap<&str, String>) -> String {
+        self.get(kind).render(vars)
+    }
+}
+
+/// Templates of a tenant, with the default ones in `language`. `user_id`
+/// identifies personal tenants.
+pub async fn get_templates(
+    tenant: Tenant,
+    user_id: u32,
+    language: Language,
+) -> Result<Templates, Error> {
+    let (tenant_type, tenant_id) = tenant.key(user_id);
+    let conn = CONN.lock().await;
+    let mut stmt = conn.prepare_cached(
+        "SELECT `kind`, `template` FROM `notification_template` \
+        WHERE `tenant_type` = ?1 AND `tenant_id` = ?2",
+    )?;
+    let rows = stmt
+        .query_map(params![tenant_type, tenant_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut templates = Templates::new(language);
+    for (key, source) in rows {
+        match (
+            TemplateKind::from_key(key.as_str()),
+            Template::parse(source.as_str()),
+        ) {
+            (Some(kind), Ok(template)) => {
+                templates.overrides.insert(kind, template);
+            }
+            (_, e) => log(
+                LogLevel::Warning,
+                "template",
+                format!("忽略 {:?} 的模板 {}：{:?}", tenant, key, e),
+            ),
+        }
+    }
+    Ok(templates)
+}
+
+/// Save a template of a tenant, or restore the default one with `None`.
+pub async fn set_template(
+    tenant: Tenant,
+    user_id: u32,
+    kind: TemplateKind,
+    template: Option<&Template>,
+) -> Result<(), Error> {
+    let (tenant_type, tenant_id) = tenant.key(user_id);
+    let conn = CONN.lock().await;
+    match template {
+        Some(template) => conn.execute(
+            "INSERT OR REPLACE INTO `notification_template` \
+            (`tenant_type`, `tenant_id`, `kind`, `template`, `updated_at`) \
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                tenant_type,
+                tenant_id,
+                kind.key(),
+                template.source(),
+                Utc::now().naive_utc()
+            ],
+        )?,
+        None => conn.execute(
+            "DELETE FROM `notification_template` \
+            WHERE `tenant_type` = ?1 AND `tenant_id` = ?2 AND `kind` = ?3",
+            params![tenant_type, tenant_id, kind.key()],
+        )?,
+    };
+    Ok(())
+}
+
+#[test]
+fn template_test() {
+    let template =
+        Template::parse("{{course_name}}：{{#if visible}}{{module_name}}{{else}}隐藏内容{{/if}}!")
+            .unwrap();
+    let mut vars = HashMap::new();
+    vars.insert("course_name", "高数".to_string());
+    vars.insert("module_name", "讲义".to_string());
+    vars.insert("visible", "true".to_string());
+    assert_eq!(template.render(&vars), "高数：讲义!");
+    vars.insert("visible", "false".to_string());
+    assert_eq!(template.render(&vars), "高数：隐藏内容!");
+    assert!(Template::parse("{{name}}").is_err());
+    assert!(Template::parse("{{#if visible}}").is_err());
+    assert!(Template::parse("{{/if}}").is_err());
+    assert!(Template::parse("{{course_name").is_err());
+    for kind in TEMPLATE_KINDS {
+        assert_eq!(
+            TemplateKind::find(kind.name(Language::Chinese)),
+            Some(*kind)
+        );
+        assert_eq!(
+            TemplateKind::find(kind.name(Language::English)),
+            Some(*kind)
+        );
+        for language in LANGUAGES {
+            assert!(Template::parse(kind.default_source(*language)).is_ok());
+        }
+    }
+}
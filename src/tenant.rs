@@ -1,6 +1,25 @@
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Tenant {
-    #[allow(unused)]
     SenderSelf,
     Group(i64),
+    /// A Telegram chat, either private or a group
+    Telegram(i64),
+    /// A webhook configured in `config.json`, keyed by its ID
+    Webhook(i64),
+    /// Mailbox of a user, keyed by user ID
+    Email(u32),
+}
+
+impl Tenant {
+    /// Type and ID identifying the tenant in tables shared by all kinds of
+    /// tenants. Personal tenants are identified by their user.
+    pub fn key(&self, user_id: u32) -> (&'static str, i64) {
+        match *self {
+            Tenant::SenderSelf => ("self", user_id as i64),
+            Tenant::Group(group_qq) => ("group", group_qq),
+            Tenant::Telegram(chat_id) => ("telegram", chat_id),
+            Tenant::Webhook(webhook_id) => ("webhook", webhook_id),
+            Tenant::Email(user_id) => ("email", user_id as i64),
+        }
+    }
 }
@@ -1,13 +1,105 @@
+use crate::config::CONFIG;
+use crate::credential::{decrypt_credential, encrypt_credential};
 use crate::error::Error;
+use crate::moodle;
+use crate::tenant::Tenant;
 use crate::CONN;
-use rusqlite::Connection;
+use rusqlite::{params, Connection, OptionalExtension};
+use zeroize::Zeroize;
 
-pub async fn get_user_id_from_qq(qq: i64) -> Result<u32, Error> {
+/// Chat account a user talks to the bot from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Account {
+    Qq(i64),
+    Telegram(i64),
+}
+
+impl Account {
+    fn column(&self) -> &'static str {
+        match self {
+            Account::Qq(_) => "qq",
+            Account::Telegram(_) => "telegram_id",
+        }
+    }
+
+    fn id(&self) -> i64 {
+        match *self {
+            Account::Qq(id) | Account::Telegram(id) => id,
+        }
+    }
+}
+
+pub async fn get_user_id(account: Account) -> Result<u32, Error> {
     let conn = CONN.lock().await;
     let mut stmt = conn
-        .prepare_cached("SELECT `id` FROM `user` WHERE `qq` = ?1")
+        .prepare_cached(
+            format!("SELECT `id` FROM `user` WHERE `{}` = ?1", account.column()).as_str(),
+        )
         .unwrap();
-    Ok(stmt.query_row(&[qq], |row| Ok(row.get(0)?))?)
+    Ok(stmt.query_row([account.id()], |row| row.get(0))?)
+}
+
+/// Whether the user of an account is banned. Accounts without a user are
+/// not.
+pub async fn is_banned(account: Account) -> Result<bool, Error> {
+    let conn = CONN.lock().await;
+    let banned = conn
+        .query_row(
+            format!(
+                "SELECT `banned` FROM `user` WHERE `{}` = ?1",
+                account.column()
+            )
+            .as_str(),
+            params![account.id()],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(banned.unwrap_or(false))
+}
+
+/// Where to reach a user in private, with the QQ of the receiver for
+/// `Tenant::SenderSelf`
+pub fn get_user_contact(conn: &Connection, user_id: u32) -> Result<(Tenant, i64), Error> {
+    let (qq, telegram_id): (Option<i64>, Option<i64>) = conn
+        .prepare_cached("SELECT `qq`, `telegram_id` FROM `user` WHERE `id` = ?1")?
+        .query_row([user_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    match (qq, telegram_id) {
+        (Some(qq), _) => Ok((Tenant::SenderSelf, qq)),
+        (None, Some(telegram_id)) => Ok((Tenant::Telegram(telegram_id), 0)),
+        (None, None) => Err(Error::NotExist),
+    }
+}
+
+/// Email address of a user, and whether they prefer a daily digest
+pub async fn get_user_email(user_id: u32) -> Result<Option<(String, bool)>, Error> {
+    let conn = CONN.lock().await;
+    let (email, digest): (Option<String>, bool) = conn
+        .prepare_cached("SELECT `email`, `email_digest` FROM `user` WHERE `id` = ?1")?
+        .query_row([user_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    Ok(email.map(|e| (e, digest)))
+}
+
+/// Set or clear the email address of a user. Clearing it also removes the
+/// email subscriptions and pending digest of the user.
+pub async fn set_user_email(user_id: u32, email: Option<&str>, digest: bool) -> Result<(), Error> {
+    let mut conn = CONN.lock().await;
+    let tx = conn.transaction()?;
+    tx.execute(
+        "UPDATE `user` SET `email` = ?1, `email_digest` = ?2 WHERE `id` = ?3",
+        params![email, digest, user_id],
+    )?;
+    if email.is_none() {
+        tx.execute(
+            "DELETE FROM `user_course_email` WHERE `user_id` = ?1",
+            params![user_id],
+        )?;
+        tx.execute(
+            "DELETE FROM `email_digest` WHERE `user_id` = ?1",
+            params![user_id],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
 }
 
 pub fn get_user_moodle_token(conn: &Connection, user_id: u32) -> Result<String, Error> {
@@ -15,5 +107,117 @@ pub fn get_user_moodle_token(conn: &Connection, user_id: u32) -> Result<String,
         .prepare_cached("SELECT `moodle_token` FROM `user` WHERE `id` = ?1")
         .unwrap();
     Ok(stmt.query_row(&[user_id], |row| Ok(row.get(0)?))?)
-    // TODO: refresh token
+}
+
+/// Saved credentials of a user who opted in to automatic token renewal
+pub fn get_user_credential(
+    conn: &Connection,
+    user_id: u32,
+) -> Result<Option<(String, String)>, Error> {
+    let credential: Option<Vec<u8>> = conn
+        .prepare_cached("SELECT `credential` FROM `user` WHERE `id` = ?1")?
+        .query_row([user_id], |row| row.get(0))?;
+    credential
+        .map(|c| decrypt_credential(c.as_slice()))
+        .transpose()
+}
+
+pub fn save_user_token(conn: &Connection, user_id: u32, token: &str) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE `user` SET `moodle_token` = ?1, `token_expired` = 0 WHERE `id` = ?2",
+        params![token, user_id],
+    )?;
+    Ok(())
+}
+
+/// Mark the token of a user as expired, which pauses their subscriptions.
+/// Returns false if the token has been changed or marked already.
+pub fn set_token_expired(conn: &Connection, user_id: u32, token: &str) -> Result<bool, Error> {
+    Ok(conn.execute(
+        "UPDATE `user` SET `token_expired` = 1 \
+        WHERE `id` = ?1 AND `moodle_token` = ?2 AND `token_expired` = 0",
+        params![user_id, token],
+    )? > 0)
+}
+
+/// Log in to Moodle and return the token. Credentials are wiped from memory
+/// as soon as the request is done.
+pub async fn login_moodle(mut campus_id: String, mut password: String) -> Result<String, Error> {
+    let login_result = moodle::login(campus_id.as_str(), password.as_str()).await;
+    campus_id.zeroize();
+    password.zeroize();
+    Ok(login_result?.token)
+}
+
+/// Log in to Moodle and save the token of the user, creating the user if
+/// necessary. With `remember`, credentials are saved encrypted to renew the
+/// token automatically. Returns the user ID and the number of subscriptions
+/// resumed from an expired token.
+pub async fn login_user(
+    account: Account,
+    nickname: &str,
+    campus_id: String,
+    password: String,
+    remember: bool,
+) -> Result<(u32, usize), Error> {
+    let credential = if remember {
+        Some(encrypt_credential(campus_id.as_str(), password.as_str())?)
+    } else {
+        None
+    };
+    let token = login_moodle(campus_id, password).await?;
+
+    let conn = CONN.lock().await;
+    let existing: Option<(u32, bool)> = conn
+        .query_row(
+            format!(
+                "SELECT `id`, `token_expired` FROM `user` WHERE `{}` = ?1",
+                account.column()
+            )
+            .as_str(),
+            params![account.id()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    match existing {
+        Some((user_id, token_expired)) => {
+            conn.execute(
+                "UPDATE `user` SET `moodle_token` = ?1, `nickname` = ?2, `credential` = ?3, \
+                `token_expired` = 0 WHERE `id` = ?4",
+                params![token, nickname, credential, user_id],
+            )?;
+            // Subscriptions paused by failures stay paused
+            let resumed = if token_expired {
+                conn.query_row(
+                    "SELECT (SELECT COUNT(*) FROM `user_course_group` \
+                    WHERE `user_id` = ?1 AND `failure_count` < ?2) \
+                    + (SELECT COUNT(*) FROM `user_course_self` \
+                    WHERE `user_id` = ?1 AND `failure_count` < ?2) \
+                    + (SELECT COUNT(*) FROM `user_course_telegram` \
+                    WHERE `user_id` = ?1 AND `failure_count` < ?2) \
+                    + (SELECT COUNT(*) FROM `user_course_webhook` \
+                    WHERE `user_id` = ?1 AND `failure_count` < ?2) \
+                    + (SELECT COUNT(*) FROM `user_course_email` \
+                    WHERE `user_id` = ?1 AND `failure_count` < ?2)",
+                    params![user_id, CONFIG.schedule.failure_threshold],
+                    |row| row.get::<_, i64>(0),
+                )? as usize
+            } else {
+                0
+            };
+            Ok((user_id, resumed))
+        }
+        None => {
+            conn.execute(
+                format!(
+                    "INSERT INTO `user` (`{}`, `nickname`, `moodle_token`, `credential`) \
+                    VALUES (?1, ?2, ?3, ?4)",
+                    account.column()
+                )
+                .as_str(),
+                params![account.id(), nickname, token, credential],
+            )?;
+            Ok((conn.last_insert_rowid() as u32, 0))
+        }
+    }
 }